@@ -0,0 +1,94 @@
+use std::{collections::BTreeMap, sync::OnceLock, time::Instant};
+
+use anyhow::Result;
+use clap::ValueEnum;
+
+use crate::{
+    command,
+    manifest::QdiscStats,
+    methodology::{Counters, Impairment, Offloads},
+    netlink,
+    runner::{self, Invocation},
+};
+
+/// How the topology is configured
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Backend {
+    /// Running ip, tc, ethtool and nft
+    Command,
+    /// Over rtnetlink, ethtool netlink and nfnetlink directly
+    Netlink,
+}
+
+static BACKEND: OnceLock<Backend> = OnceLock::new();
+
+/// Picks the backend every later call goes through, set once at startup
+pub fn configure(backend: Backend) {
+    let _ = BACKEND.set(backend);
+}
+
+fn backend() -> Backend {
+    *BACKEND.get_or_init(|| Backend::Command)
+}
+
+/// Runs a netlink operation, recording it in the transcript as the command
+/// backend records the commands it runs, as `netlink <operation> <args>`
+fn record<T>(args: Vec<String>, operation: impl FnOnce() -> Result<T>) -> Result<T> {
+    let start = Instant::now();
+    let result = operation();
+    runner::record(Invocation {
+        netns: None,
+        program: "netlink".into(),
+        args,
+        status: Some(result.is_err().into()),
+        timed_out: false,
+        duration: start.elapsed().as_secs_f64(),
+        stdout: String::new(),
+        stderr: match &result {
+            Ok(_) => String::new(),
+            Err(e) => format!("{e:#}"),
+        },
+    });
+    result
+}
+
+/// Forwards each function to the module of the backend in use, which both
+/// define with the same signature
+macro_rules! dispatch {
+    ($(fn $name:ident($($arg:ident: $type:ty),*) -> $output:ty;)*) => {$(
+        pub fn $name($($arg: $type),*) -> $output {
+            match backend() {
+                Backend::Command => command::$name($($arg),*),
+                Backend::Netlink => {
+                    let args = vec![
+                        stringify!($name).into(),
+                        $(format!("{}={:?}", stringify!($arg), $arg)),*
+                    ];
+                    record(args, || netlink::$name($($arg),*))
+                }
+            }
+        }
+    )*};
+}
+
+dispatch! {
+    fn add_netns(netns: &str) -> Result<()>;
+    fn del_netns(netns: &str) -> Result<()>;
+    fn add_veth(end: &str, netns: &str, peer: &str, peer_netns: &str) -> Result<()>;
+    fn add_address(netns: &str, end: &str, address: &str) -> Result<()>;
+    fn set_up(netns: &str, end: &str, mtu: u32) -> Result<()>;
+    fn set_offloads(netns: &str, end: &str, offloads: &Offloads) -> Result<()>;
+    fn features(netns: &str, end: &str) -> Result<BTreeMap<String, String>>;
+    fn add_netem(netns: &str, end: &str, impairment: &Impairment) -> Result<()>;
+    fn qdisc_stats(netns: &str, end: &str) -> Result<QdiscStats>;
+    fn qdiscs(netns: &str) -> Result<String>;
+    fn del_qdisc(netns: &str, end: &str) -> Result<()>;
+    fn add_table(netns: &str) -> Result<()>;
+    fn add_periodic_loss(netns: &str, mtu: Option<u32>, modulus: u32) -> Result<()>;
+    fn periodic_loss_counters(netns: &str) -> Result<Counters>;
+    fn add_oversized_counter(netns: &str, mtu: u32) -> Result<()>;
+    fn oversized_counter(netns: &str) -> Result<Option<u64>>;
+    fn del_oversized_counter(netns: &str) -> Result<()>;
+    fn ruleset(netns: &str) -> Result<String>;
+    fn flush_ruleset(netns: &str) -> Result<()>;
+}
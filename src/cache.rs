@@ -0,0 +1,342 @@
+use std::{
+    collections::BTreeMap,
+    fs::{read_to_string, remove_file, rename, File},
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    manifest::{tcp_cubic, Manifest, Run},
+    methodology::{Methodology, Sender, Topology},
+    probe, read,
+    runner::{self, Invocation},
+    save, Measurement,
+};
+
+/// Everything that affects the outcome of a single run
+#[derive(Serialize, Deserialize)]
+pub struct Config {
+    pub methodology: String,
+    pub settings: Value,
+    pub mtu: u32,
+    /// Congestion control of the sending socket, the system default if unset
+    pub congestion: Option<String>,
+    pub rtt: f64,
+    pub loss: f64,
+    /// Duration of the run, in seconds
+    pub duration: f64,
+    /// Interval between two measurements, in seconds
+    pub interval: f64,
+    /// tcp_info fields recorded alongside the congestion window
+    #[serde(default)]
+    pub fields: Vec<String>,
+    /// Whether the congestion window was traced on every ACK
+    #[serde(default)]
+    pub probe: bool,
+    /// Kernel parameters in effect in the client namespace
+    pub kernel: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Captures the configuration of a run, which must happen after the
+    /// methodology has been set up
+    pub fn new(
+        methodology: &dyn Methodology,
+        topology: &Topology,
+        sender: &Sender,
+        r: f64,
+        p: f64,
+    ) -> Result<Self> {
+        Ok(Self {
+            methodology: methodology.name().into(),
+            settings: methodology.settings(),
+            mtu: topology.mtu,
+            congestion: sender.congestion.clone(),
+            rtt: r,
+            loss: p,
+            duration: sender.duration.as_secs_f64(),
+            interval: sender.interval.as_secs_f64(),
+            fields: sender.fields.iter().map(|e| e.to_string()).collect(),
+            probe: sender.probe,
+            kernel: kernel()?,
+        })
+    }
+
+    /// FNV-1a hash of the serialized configuration
+    pub fn key(&self) -> Result<String> {
+        let mut hash = 0xcbf29ce484222325u64;
+        for byte in serde_json::to_vec(self)? {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+        Ok(format!("{hash:016x}"))
+    }
+}
+
+fn kernel() -> Result<BTreeMap<String, String>> {
+    let mut kernel = BTreeMap::new();
+    for (name, value) in tcp_cubic()? {
+        kernel.insert(format!("tcp_cubic.{name}"), value);
+    }
+    let value = read_to_string("/proc/sys/net/ipv4/tcp_congestion_control")?;
+    kernel.insert(
+        "net.ipv4.tcp_congestion_control".into(),
+        value.trim().into(),
+    );
+    Ok(kernel)
+}
+
+/// Extensions of the files of a run: its measurements, tcp_probe trace,
+/// transcript and manifest
+const EXTENSIONS: [&str; 4] = ["csv", "probe.csv", "commands.json", "json"];
+
+/// Every run is stored as `{r}_{p}.{key}.{extension}`, and `{r}_{p}.{extension}`
+/// links to the run of the configuration last simulated or looked up, which
+/// is what everything but the cache reads
+fn path(out: &Path, r: f64, p: f64, key: Option<&str>, extension: &str) -> PathBuf {
+    match key {
+        Some(key) => out.join(format!("{r}_{:.5}.{key}.{extension}", p)),
+        None => out.join(format!("{r}_{:.5}.{extension}", p)),
+    }
+}
+
+fn is_file(path: &Path) -> bool {
+    path.symlink_metadata().is_ok_and(|e| e.is_file())
+}
+
+/// Reads a manifest, if it is readable
+fn read_manifest(path: &Path) -> Option<Manifest> {
+    serde_json::from_str(&read_to_string(path).ok()?).ok()
+}
+
+/// Loads a cached run, which only hits if it was produced by the exact same
+/// configuration
+pub fn lookup(out: &Path, config: &Config) -> Result<Option<Vec<Measurement>>> {
+    let (r, p, key) = (config.rtt, config.loss, config.key()?);
+    // runs stored before their files were keyed sit under plain names
+    let key = [Some(key.as_str()), None]
+        .into_iter()
+        .find(|e| read_manifest(&path(out, r, p, *e, "json")).is_some_and(|e| e.key == key));
+    let Some(key) = key else {
+        return Ok(None);
+    };
+    if config.probe && !path(out, r, p, key, "probe.csv").exists() {
+        return Ok(None);
+    }
+    Ok(read(&path(out, r, p, key, "csv")).ok())
+}
+
+/// Reads the manifest of a run, if it has a readable one
+pub fn manifest(out: &Path, r: f64, p: f64) -> Option<Manifest> {
+    read_manifest(&path(out, r, p, None, "json"))
+}
+
+/// Moves the files of a run found under plain names, as written before runs
+/// were keyed or by `store`, under the key of their manifest and links them
+/// back
+fn adopt(out: &Path, r: f64, p: f64) -> Result<()> {
+    let manifest = path(out, r, p, None, "json");
+    if !is_file(&manifest) {
+        return Ok(());
+    }
+    let Some(manifest) = read_manifest(&manifest) else {
+        return Ok(());
+    };
+    for extension in EXTENSIONS {
+        let file = path(out, r, p, None, extension);
+        if is_file(&file) {
+            rename(&file, path(out, r, p, Some(&manifest.key), extension))?;
+        }
+    }
+    select(out, r, p, &manifest.key)
+}
+
+/// Clears the plain names of a run once it is adopted, removing links and
+/// moving the files of a run without a manifest, such as those of the
+/// committed result directories, aside as `{r}_{p}.legacy.{extension}`
+fn unlink(out: &Path, r: f64, p: f64) -> Result<()> {
+    for extension in EXTENSIONS {
+        let link = path(out, r, p, None, extension);
+        if is_file(&link) {
+            let legacy = path(out, r, p, Some("legacy"), extension);
+            if legacy.symlink_metadata().is_ok() {
+                bail!(
+                    "{} has no manifest, and {} is already taken",
+                    link.display(),
+                    legacy.display()
+                );
+            }
+            rename(&link, legacy)?;
+        } else if link.symlink_metadata().is_ok() {
+            remove_file(&link)?;
+        }
+    }
+    Ok(())
+}
+
+/// Points the plain names of a run at the files stored under `key`
+pub fn select(out: &Path, r: f64, p: f64, key: &str) -> Result<()> {
+    adopt(out, r, p)?;
+    unlink(out, r, p)?;
+    for extension in EXTENSIONS {
+        let link = path(out, r, p, None, extension);
+        let target = path(out, r, p, Some(key), extension);
+        if let (true, Some(name)) = (target.exists(), target.file_name()) {
+            symlink(name, &link)?;
+        }
+    }
+    Ok(())
+}
+
+/// Saves a run along with its manifest
+pub fn store(out: &Path, config: Config, run: Run, setup: &[Invocation]) -> Result<()> {
+    let (r, p) = (config.rtt, config.loss);
+    // keeps the run found under the plain names, and removes the links that
+    // would otherwise be written through
+    adopt(out, r, p)?;
+    unlink(out, r, p)?;
+    save(out, r, p, &run.measurements)?;
+    if let Some(events) = &run.probe {
+        probe::save(out, r, p, events)?;
+    }
+    runner::save(out, r, p, setup, &run.commands)?;
+    let manifest = Manifest {
+        key: config.key()?,
+        config,
+        system: Some(run.system),
+        timing: Some(run.timing),
+        drops: run.drops,
+        netem: run.netem,
+        warnings: run.warnings,
+    };
+    let file = File::create(path(out, r, p, None, "json"))?;
+    serde_json::to_writer_pretty(file, &manifest)?;
+    adopt(out, r, p)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env::temp_dir,
+        fs::{create_dir_all, read_link, remove_dir_all, write},
+        process,
+    };
+
+    use super::*;
+    use crate::manifest::{System, Timing};
+
+    const R: f64 = 0.1;
+    const P: f64 = 0.001;
+
+    fn config(methodology: &str) -> Config {
+        Config {
+            methodology: methodology.into(),
+            settings: Value::Null,
+            mtu: 1500,
+            congestion: None,
+            rtt: R,
+            loss: P,
+            duration: 1.0,
+            interval: 0.1,
+            fields: Vec::new(),
+            probe: false,
+            kernel: BTreeMap::new(),
+        }
+    }
+
+    const CSV: &str = "time,bytes_transferred,congestion_window\n0.1,0,10\n0.2,1000,10\n";
+
+    /// Writes a run under plain names, as runs were before they were keyed
+    fn write_run(out: &Path, config: Config) {
+        write(path(out, R, P, None, "csv"), CSV).unwrap();
+        let manifest = Manifest {
+            key: config.key().unwrap(),
+            config,
+            system: None,
+            timing: None,
+            drops: None,
+            netem: None,
+            warnings: Vec::new(),
+        };
+        let file = File::create(path(out, R, P, None, "json")).unwrap();
+        serde_json::to_writer(file, &manifest).unwrap();
+    }
+
+    fn run() -> Run {
+        let measurement = |time, bytes_transferred| Measurement {
+            time,
+            bytes_transferred,
+            congestion_window: 10,
+            fields: Vec::new(),
+        };
+        Run {
+            measurements: vec![measurement(0.1, 0), measurement(0.2, 1000)],
+            probe: None,
+            system: System {
+                kernel: String::new(),
+                tcp_cubic: BTreeMap::new(),
+                offloads: BTreeMap::new(),
+                qdisc: String::new(),
+                ruleset: String::new(),
+                congestion: "cubic".into(),
+            },
+            timing: Timing {
+                start: 0.0,
+                end: 1.0,
+                elapsed: 1.0,
+            },
+            drops: None,
+            netem: None,
+            commands: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn keyed_runs() {
+        let out = temp_dir().join(format!("csee-cache-{}", process::id()));
+        create_dir_all(&out).unwrap();
+        let (a, b) = (config("a"), config("b"));
+        let (key_a, key_b) = (a.key().unwrap(), b.key().unwrap());
+        let csv = path(&out, R, P, None, "csv");
+        let target = |key: &str| PathBuf::from(format!("0.1_0.00100.{key}.csv"));
+
+        // a run stored before runs were keyed is found under plain names
+        write_run(&out, config("a"));
+        assert!(lookup(&out, &a).unwrap().is_some());
+        assert!(lookup(&out, &b).unwrap().is_none());
+
+        // and is kept under its key once another configuration is stored
+        store(&out, config("b"), run(), &[]).unwrap();
+        assert_eq!(read_link(&csv).unwrap(), target(&key_b));
+        assert!(lookup(&out, &a).unwrap().is_some());
+        assert!(lookup(&out, &b).unwrap().is_some());
+
+        // selecting a cached run points the plain names back at it
+        select(&out, R, P, &key_a).unwrap();
+        assert_eq!(read_link(&csv).unwrap(), target(&key_a));
+        assert_eq!(manifest(&out, R, P).unwrap().key, key_a);
+
+        // a run without a manifest, as in the committed result directories,
+        // is a miss, and is moved aside rather than overwritten
+        unlink(&out, R, P).unwrap();
+        write(&csv, CSV).unwrap();
+        let c = config("c");
+        assert!(lookup(&out, &c).unwrap().is_none());
+        store(&out, config("c"), run(), &[]).unwrap();
+        let legacy = path(&out, R, P, Some("legacy"), "csv");
+        assert_eq!(read_to_string(&legacy).unwrap(), CSV);
+        assert!(lookup(&out, &c).unwrap().is_some());
+
+        // unless another one already was
+        unlink(&out, R, P).unwrap();
+        write(&csv, "").unwrap();
+        assert!(store(&out, config("d"), run(), &[]).is_err());
+        assert_eq!(read_to_string(&legacy).unwrap(), CSV);
+        remove_dir_all(&out).unwrap();
+    }
+}
@@ -0,0 +1,761 @@
+use std::{fs::write, ops::Range, path::Path, str::FromStr};
+
+use anyhow::{bail, Context, Error, Result};
+use clap::{Args, ValueEnum};
+use plotters::{
+    coord::{
+        ranged1d::{DefaultFormatting, KeyPointHint},
+        types::RangedCoordf64,
+        Shift,
+    },
+    prelude::*,
+};
+
+use serde::Serialize;
+
+use crate::{model::Models, probe::Event, LossAxis, Measurement, Summary};
+
+/// Ranges, scales, size and fonts of the charts. Ranges left unset are fitted
+/// to the data.
+#[derive(Args, Clone)]
+pub struct Style {
+    /// Time range of the charts of runs, in RTTs
+    #[arg(long, value_name = "MIN,MAX", default_value = ",")]
+    pub time_range: Bounds,
+    /// Congestion window range of the charts of runs, in packets
+    #[arg(long, value_name = "MIN,MAX", default_value = ",")]
+    pub cwnd_range: Bounds,
+    /// Packet loss rate range of the throughput charts
+    #[arg(long, value_name = "MIN,MAX", default_value = ",")]
+    pub loss_range: Bounds,
+    /// Throughput range of the throughput charts, in MB/s
+    #[arg(long, value_name = "MIN,MAX", default_value = ",")]
+    pub throughput_range: Bounds,
+    /// Plot packet loss rates on a logarithmic scale
+    #[arg(long)]
+    pub log_loss: bool,
+    /// Plot throughputs on a logarithmic scale
+    #[arg(long)]
+    pub log_throughput: bool,
+    /// Size of the charts, in pixels. The stacked charts of `compare` are
+    /// this high per pair of panels.
+    #[arg(long, value_name = "WIDTHxHEIGHT", default_value = "1440x720")]
+    pub size: Size,
+    /// Formats the charts are written in, as a comma separated list
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "png")]
+    pub format: Vec<Format>,
+    /// Font family of the charts
+    #[arg(long, default_value = "sans-serif")]
+    pub font: String,
+    /// Font size of the labels, in pixels, captions being a third larger
+    #[arg(long, default_value_t = 24)]
+    pub font_size: u32,
+}
+
+impl Style {
+    fn caption(&self) -> (&str, u32) {
+        (&self.font, self.font_size * 4 / 3)
+    }
+
+    fn label(&self) -> (&str, u32) {
+        (&self.font, self.font_size)
+    }
+}
+
+/// Bounds of an axis, as `MIN,MAX`, either of which may be left empty to
+/// fit it to the data
+#[derive(Clone, Copy, Default)]
+pub struct Bounds {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl FromStr for Bounds {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (min, max) = s.split_once(',').context("expected MIN,MAX")?;
+        let bound = |e: &str| match e.trim() {
+            "" => Ok(None),
+            e => e.parse().map(Some),
+        };
+        let bounds = Self {
+            min: bound(min)?,
+            max: bound(max)?,
+        };
+        if let Self {
+            min: Some(min),
+            max: Some(max),
+        } = bounds
+        {
+            if min >= max {
+                bail!("{min} is not below {max}");
+            }
+        }
+        Ok(bounds)
+    }
+}
+
+impl Bounds {
+    /// Range of an axis showing `values`, from zero if `zero` unless
+    /// logarithmic, with the bounds that are set taking precedence
+    fn fit(
+        self,
+        values: impl IntoIterator<Item = f64>,
+        zero: bool,
+        log: bool,
+    ) -> Result<Range<f64>> {
+        let values = values
+            .into_iter()
+            .filter(|e| e.is_finite() && (*e > 0.0 || !log));
+        let (mut min, mut max) = values
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), e| {
+                (min.min(e), max.max(e))
+            });
+        if min > max {
+            (min, max) = (1.0, 10.0);
+        }
+        if zero {
+            // leaves room above the highest point, and below the lowest on a
+            // logarithmic scale
+            max *= 1.05;
+            min = match log {
+                true => min / 1.05,
+                false => min.min(0.0),
+            };
+        }
+        if min == max {
+            (min, max) = match (log, min.abs()) {
+                (true, _) => (min / 2.0, max * 2.0),
+                (false, 0.0) => (0.0, 1.0),
+                (false, e) => (min - e / 2.0, max + e / 2.0),
+            };
+        }
+        let range = self.min.unwrap_or(min)..self.max.unwrap_or(max);
+        if range.start >= range.end {
+            bail!("empty range {}..{}", range.start, range.end);
+        }
+        if log && range.start <= 0.0 {
+            bail!(
+                "a logarithmic scale needs a positive range, not {}..{}",
+                range.start,
+                range.end
+            );
+        }
+        Ok(range)
+    }
+}
+
+/// Size of a chart, as `WIDTHxHEIGHT`
+#[derive(Clone, Copy)]
+pub struct Size(pub u32, pub u32);
+
+impl FromStr for Size {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (width, height) = s.split_once('x').context("expected WIDTHxHEIGHT")?;
+        Ok(Self(width.parse()?, height.parse()?))
+    }
+}
+
+/// A linear or logarithmic axis, which plotters only tells apart by type
+enum Axis {
+    Linear(RangedCoordf64),
+    Log(LogCoord<f64>),
+}
+
+impl Axis {
+    fn new(range: Range<f64>, log: bool) -> Self {
+        match log {
+            true => Axis::Log(range.log_scale().into()),
+            false => Axis::Linear(range.into()),
+        }
+    }
+}
+
+impl Ranged for Axis {
+    type FormatOption = DefaultFormatting;
+    type ValueType = f64;
+
+    fn map(&self, value: &f64, limit: (i32, i32)) -> i32 {
+        match self {
+            Axis::Linear(e) => e.map(value, limit),
+            Axis::Log(e) => e.map(value, limit),
+        }
+    }
+
+    fn key_points<Hint: KeyPointHint>(&self, hint: Hint) -> Vec<f64> {
+        match self {
+            Axis::Linear(e) => e.key_points(hint),
+            Axis::Log(e) => {
+                // plotters starts from the first power of ten in range, which
+                // leaves a range of less than a decade with a single label
+                let range = e.range();
+                let decades = range.start.log10().floor() as i32..=range.end.log10().ceil() as i32;
+                let steps: [&[f64]; 3] =
+                    [&[1., 2., 3., 4., 5., 6., 7., 8., 9.], &[1., 2., 5.], &[1.]];
+                steps
+                    .into_iter()
+                    .map(|steps| {
+                        decades
+                            .clone()
+                            .flat_map(|e| steps.iter().map(move |step| step * 10f64.powi(e)))
+                            .filter(|e| (range.start..=range.end).contains(e))
+                            .collect::<Vec<_>>()
+                    })
+                    .find(|e| e.len() <= hint.max_num_points())
+                    .unwrap_or_default()
+            }
+        }
+    }
+
+    fn range(&self) -> Range<f64> {
+        match self {
+            Axis::Linear(e) => e.range(),
+            Axis::Log(e) => e.range(),
+        }
+    }
+}
+
+/// Decimals the labels of an axis need to tell its key points apart
+fn decimals(range: &Range<f64>, log: bool) -> usize {
+    let step = match log {
+        true => range.start,
+        false => (range.end - range.start) / 5.0,
+    };
+    (-step.log10().floor()).max(0.0) as usize
+}
+
+/// Axis of a figure
+#[derive(Serialize)]
+struct Scale {
+    label: &'static str,
+    min: f64,
+    max: f64,
+    log: bool,
+    /// Factor values are labelled multiplied by, such as 100 for percentages
+    factor: f64,
+}
+
+impl Scale {
+    fn new(label: &'static str, range: Range<f64>, log: bool) -> Self {
+        Self {
+            label,
+            min: range.start,
+            max: range.end,
+            log,
+            factor: 1.0,
+        }
+    }
+
+    fn axis(&self) -> Axis {
+        Axis::new(self.min..self.max, self.log)
+    }
+
+    fn decimals(&self) -> usize {
+        decimals(&(self.min * self.factor..self.max * self.factor), self.log)
+    }
+}
+
+#[derive(Serialize)]
+struct Series {
+    /// Legend entry, if any
+    name: Option<String>,
+    color: (u8, u8, u8),
+    opacity: f64,
+    width: u32,
+    points: Vec<(f64, f64)>,
+}
+
+impl Series {
+    fn new(name: impl Into<String>, color: usize, points: Vec<(f64, f64)>) -> Self {
+        Self {
+            name: Some(name.into()),
+            color: Palette99::pick(color).rgb(),
+            opacity: 1.0,
+            width: 1,
+            points,
+        }
+    }
+
+    fn style(&self) -> ShapeStyle {
+        let (r, g, b) = self.color;
+        RGBColor(r, g, b).mix(self.opacity).stroke_width(self.width)
+    }
+}
+
+/// A line chart, or a panel of stacked ones, which is drawn with plotters
+/// and embedded as is in HTML pages
+#[derive(Serialize)]
+struct Figure {
+    /// Caption of a panel
+    caption: Option<String>,
+    x: Scale,
+    y: Scale,
+    series: Vec<Series>,
+}
+
+impl Figure {
+    fn draw<DB: DrawingBackend>(
+        &self,
+        area: &DrawingArea<DB, Shift>,
+        style: &Style,
+        bottom: bool,
+    ) -> Result<()>
+    where
+        DB::ErrorType: 'static,
+    {
+        let mut builder = ChartBuilder::on(area);
+        if let Some(caption) = &self.caption {
+            builder.caption(caption, style.label());
+        }
+        // only the bottom panel of a stack labels the shared x axis
+        let mut chart = builder
+            .x_label_area_size(if bottom { style.font_size * 8 / 3 } else { 0 })
+            .y_label_area_size(style.font_size * 4)
+            .margin_right(32)
+            .build_cartesian_2d(self.x.axis(), self.y.axis())?;
+        let (x, y) = (self.x.decimals(), self.y.decimals());
+        let (fx, fy) = (self.x.factor, self.y.factor);
+        let x_format = |e: &f64| format!("{:.x$}", e * fx);
+        let y_format = |e: &f64| format!("{:.y$}", e * fy);
+        let mut mesh = chart.configure_mesh();
+        mesh.y_desc(self.y.label)
+            .label_style(style.label())
+            .x_label_formatter(&x_format)
+            .y_label_formatter(&y_format);
+        if bottom {
+            mesh.x_desc(self.x.label);
+        }
+        mesh.draw()?;
+        for series in &self.series {
+            let line = series.style();
+            let drawn = chart.draw_series(LineSeries::new(series.points.iter().copied(), line))?;
+            if let Some(name) = &series.name {
+                drawn
+                    .label(name)
+                    .legend(move |(x, y)| PathElement::new([(x, y), (x + 24, y)], line));
+            }
+        }
+        if self.series.iter().any(|e| e.name.is_some()) {
+            chart
+                .configure_series_labels()
+                .label_font(style.label())
+                .border_style(BLACK)
+                .draw()?;
+        }
+        Ok(())
+    }
+}
+
+/// Format of the charts
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    Png,
+    Svg,
+    /// Self-contained page to zoom and pan the charts in, and read values off
+    Html,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Svg => "svg",
+            Format::Html => "html",
+        }
+    }
+}
+
+/// Interactive chart, as embedded in its page
+#[derive(Serialize)]
+struct Page<'a> {
+    title: &'a str,
+    width: u32,
+    height: u32,
+    font: &'a str,
+    font_size: u32,
+    panels: &'a [Figure],
+}
+
+/// Figures drawn under a title, stacked if several
+pub struct Chart {
+    /// File name, without extension
+    pub name: String,
+    pub title: String,
+    panels: Vec<Figure>,
+}
+
+impl Chart {
+    fn size(&self, style: &Style) -> (u32, u32) {
+        // panels of a stack are half as high as a chart of their own
+        let (width, height) = (style.size.0, style.size.1);
+        match self.panels.len() {
+            1 => (width, height),
+            n => (width, height * n as u32 / 2),
+        }
+    }
+
+    /// Writes the chart as `{name}.png`, or with the extension of every
+    /// format asked for
+    pub fn write(&self, out: &Path, style: &Style) -> Result<()> {
+        let size = self.size(style);
+        for format in &style.format {
+            let path = out.join(format!("{}.{}", self.name, format.extension()));
+            match format {
+                Format::Png => draw(BitMapBackend::new(&path, size), self, style)?,
+                Format::Svg => draw(SVGBackend::new(&path, size), self, style)?,
+                Format::Html => {
+                    let page = Page {
+                        title: &self.title,
+                        width: size.0,
+                        height: size.1 / self.panels.len() as u32,
+                        font: &style.font,
+                        font_size: style.font_size,
+                        panels: &self.panels,
+                    };
+                    // keeps labels from closing the script they are embedded in
+                    let page = serde_json::to_string(&page)?.replace('<', r"\u003c");
+                    let html = include_str!("chart.html")
+                        .replace("{title}", &escape(&self.title))
+                        .replace("{page}", &page);
+                    write(&path, html)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The chart as an SVG document
+    pub fn svg(&self, style: &Style) -> Result<String> {
+        let mut svg = String::new();
+        draw(
+            SVGBackend::with_string(&mut svg, self.size(style)),
+            self,
+            style,
+        )?;
+        Ok(svg)
+    }
+}
+
+/// Escapes text for HTML
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn draw<DB: DrawingBackend>(backend: DB, chart: &Chart, style: &Style) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    let root = backend.into_drawing_area();
+    root.fill(&WHITE)?;
+    let root = root.titled(&chart.title, style.caption())?;
+    let panels = &chart.panels;
+    let areas = root.split_evenly((panels.len(), 1));
+    for (i, (figure, area)) in panels.iter().zip(&areas).enumerate() {
+        figure.draw(area, style, i + 1 == panels.len())?;
+    }
+    root.present()?;
+    Ok(())
+}
+
+/// Events at which the congestion window changed, along with the last one
+/// before each change, which keeps the steps of a trace of every ACK
+fn changes(events: &[Event]) -> impl Iterator<Item = &Event> {
+    events.iter().enumerate().filter_map(|(i, e)| {
+        let previous = i.checked_sub(1).map(|i| events[i].snd_cwnd);
+        let next = events.get(i + 1).map(|e| e.snd_cwnd);
+        (previous != Some(e.snd_cwnd) || next != Some(e.snd_cwnd)).then_some(e)
+    })
+}
+
+/// Charts a run, along with the events traced with tcp_probe if any
+pub fn run_chart(
+    r: f64,
+    p: f64,
+    measurements: &[Measurement],
+    events: Option<&[Event]>,
+    models: &Models,
+    style: &Style,
+) -> Result<Chart> {
+    let end = measurements.last().map_or(0.0, |e| e.time);
+    let estimations = models
+        .models
+        .iter()
+        .map(|e| e.window(&models.parameters, r, p))
+        .collect::<Vec<_>>();
+    let times = style.time_range.fit([0.0, end / r], false, false)?;
+    let windows = measurements
+        .iter()
+        .map(|e| e.congestion_window as f64)
+        .chain(events.into_iter().flatten().map(|e| e.snd_cwnd as f64))
+        .chain(estimations.iter().copied());
+    let windows = style.cwnd_range.fit(windows, true, false)?;
+    let mut series = Vec::new();
+    if let Some(events) = events {
+        let events = changes(events).map(|e| (e.time / r, e.snd_cwnd as f64));
+        series.push(Series {
+            opacity: 0.4,
+            ..Series::new("Simulated (per ACK)", 0, events.collect())
+        });
+    }
+    let simulated = measurements
+        .iter()
+        .map(|e| (e.time / r, e.congestion_window as f64));
+    series.push(Series::new("Simulated", 0, simulated.collect()));
+    for (i, (model, estimation)) in models.models.iter().zip(estimations).enumerate() {
+        let points = vec![(times.start, estimation), (times.end, estimation)];
+        series.push(Series::new(model.name(), i + 1, points));
+    }
+    let figure = Figure {
+        caption: None,
+        x: Scale::new("Time (RTT)", times, false),
+        y: Scale::new("CWND (Packet)", windows, false),
+        series,
+    };
+    Ok(Chart {
+        name: format!("{r}_{:.5}", p),
+        title: format!("CWND (Packet) vs Time (RTT) (r = {r}, p = {:.5})", p),
+        panels: vec![figure],
+    })
+}
+
+/// Figure of throughput against loss rate, with axes fitted to the points, as
+/// `(r, p, throughput)`, and to the models evaluated at them
+fn throughput_figure(
+    points: &[(f64, f64, f64)],
+    loss_axis: LossAxis,
+    models: &Models,
+    style: &Style,
+    series: Vec<Series>,
+) -> Result<Figure> {
+    let losses = points.iter().map(|(_, p, _)| *p);
+    let losses = style.loss_range.fit(losses, false, style.log_loss)?;
+    let throughputs = points.iter().flat_map(|(r, p, throughput)| {
+        let estimations = models
+            .models
+            .iter()
+            .map(|e| e.throughput(&models.parameters, *r, *p));
+        estimations.chain([*throughput])
+    });
+    let throughputs = style
+        .throughput_range
+        .fit(throughputs, true, style.log_throughput)?;
+    let label = match loss_axis {
+        LossAxis::Nominal => "Packet Loss Rate (%)",
+        LossAxis::Measured => "Measured Packet Loss Rate (%)",
+    };
+    Ok(Figure {
+        caption: None,
+        x: Scale {
+            factor: 100.0,
+            ..Scale::new(label, losses, style.log_loss)
+        },
+        y: Scale::new(
+            "Average Throughput (MB/s)",
+            throughputs,
+            style.log_throughput,
+        ),
+        series,
+    })
+}
+
+/// Charts the throughputs of a result directory against the models
+pub fn summary_chart(
+    throughputs: &Summary,
+    loss_axis: LossAxis,
+    models: &Models,
+    style: &Style,
+) -> Result<Chart> {
+    let points = throughputs
+        .iter()
+        .flat_map(|(r, e)| e.iter().map(|(p, throughput)| (*r, *p, *throughput)))
+        .collect::<Vec<_>>();
+    let mut series = Vec::new();
+    for (i, (r, throughputs)) in throughputs.iter().enumerate() {
+        let r = *r;
+        let stride = models.models.len() + 1;
+        let name = format!("Simulated (r = {r})");
+        series.push(Series::new(name, stride * i, throughputs.clone()));
+        for (j, model) in models.models.iter().enumerate() {
+            let estimations = throughputs
+                .iter()
+                .map(|(p, _)| (*p, model.throughput(&models.parameters, r, *p)));
+            let name = format!("{} (r = {r})", model.name());
+            series.push(Series::new(name, stride * i + j + 1, estimations.collect()));
+        }
+    }
+    let figure = throughput_figure(&points, loss_axis, models, style, series)?;
+    let rtts = throughputs
+        .iter()
+        .map(|(r, _)| r.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    Ok(Chart {
+        name: "main".into(),
+        title: format!("Average Throughput (MB/s) vs Packet Loss Rate (%) (r = {rtts})"),
+        panels: vec![figure],
+    })
+}
+
+/// Overlays the throughput summaries of several datasets, with the models
+/// evaluated once for every RTT
+pub fn compare_summary(
+    out: &Path,
+    datasets: &[(String, Summary)],
+    loss_axis: LossAxis,
+    models: &Models,
+    style: &Style,
+) -> Result<()> {
+    let points = datasets
+        .iter()
+        .flat_map(|(_, e)| e.iter())
+        .flat_map(|(r, e)| e.iter().map(|(p, throughput)| (*r, *p, *throughput)))
+        .collect::<Vec<_>>();
+    let mut series = Vec::new();
+    let mut rtts: Vec<(f64, Vec<f64>)> = Vec::new();
+    for (i, (label, throughputs)) in datasets.iter().enumerate() {
+        for (j, (r, throughputs)) in throughputs.iter().enumerate() {
+            // tells the RTTs of a dataset apart by fading its later ones
+            series.push(Series {
+                opacity: 1.0 / (j + 1) as f64,
+                width: 2,
+                ..Series::new(format!("{label} (r = {r})"), i, throughputs.clone())
+            });
+            let losses = throughputs.iter().map(|(p, _)| *p);
+            match rtts.iter_mut().find(|(e, _)| e == r) {
+                Some((_, e)) => e.extend(losses),
+                None => rtts.push((*r, losses.collect())),
+            }
+        }
+    }
+    for (j, model) in models.models.iter().enumerate() {
+        for (r, losses) in &mut rtts {
+            losses.sort_by(f64::total_cmp);
+            losses.dedup();
+            let r = *r;
+            let estimations = losses
+                .iter()
+                .map(|p| (*p, model.throughput(&models.parameters, r, *p)));
+            let name = format!("{} (r = {r})", model.name());
+            series.push(Series::new(name, datasets.len() + j, estimations.collect()));
+        }
+    }
+    let figure = throughput_figure(&points, loss_axis, models, style, series)?;
+    let chart = Chart {
+        name: "main".into(),
+        title: "Average Throughput (MB/s) vs Packet Loss Rate (%)".into(),
+        panels: vec![figure],
+    };
+    chart.write(out, style)
+}
+
+/// Stacks the congestion windows of the same run from several datasets, one
+/// panel each, on shared axes
+pub fn compare_traces(
+    out: &Path,
+    r: f64,
+    p: f64,
+    traces: &[(String, Vec<Measurement>)],
+    style: &Style,
+) -> Result<()> {
+    let end = traces
+        .iter()
+        .filter_map(|(_, e)| e.last())
+        .fold(0.0, |end, e| e.time.max(end));
+    let times = style.time_range.fit([0.0, end / r], false, false)?;
+    let windows = traces
+        .iter()
+        .flat_map(|(_, e)| e.iter().map(|e| e.congestion_window as f64));
+    let windows = style.cwnd_range.fit(windows, true, false)?;
+    let panels = traces
+        .iter()
+        .enumerate()
+        .map(|(i, (label, measurements))| {
+            let points = measurements
+                .iter()
+                .map(|e| (e.time / r, e.congestion_window as f64));
+            Figure {
+                caption: Some(label.clone()),
+                x: Scale::new("Time (RTT)", times.clone(), false),
+                y: Scale::new("CWND (Packet)", windows.clone(), false),
+                series: vec![Series {
+                    name: None,
+                    ..Series::new("", i, points.collect())
+                }],
+            }
+        })
+        .collect::<Vec<_>>();
+    let chart = Chart {
+        name: format!("{r}_{:.5}", p),
+        title: format!("CWND (Packet) vs Time (RTT) (r = {r}, p = {:.5})", p),
+        panels,
+    };
+    chart.write(out, style)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UNSET: Bounds = Bounds {
+        min: None,
+        max: None,
+    };
+
+    #[test]
+    fn parse() {
+        let bounds: Bounds = "0.5,".parse().unwrap();
+        assert_eq!((bounds.min, bounds.max), (Some(0.5), None));
+        let bounds: Bounds = " , 2".parse().unwrap();
+        assert_eq!((bounds.min, bounds.max), (None, Some(2.0)));
+        assert!("1".parse::<Bounds>().is_err());
+        assert!("a,".parse::<Bounds>().is_err());
+        assert!("2,1".parse::<Bounds>().is_err());
+    }
+
+    #[test]
+    fn fit() {
+        let values = [1.0, 2.0, 4.0];
+        assert_eq!(UNSET.fit(values, false, false).unwrap(), 1.0..4.0);
+        assert_eq!(UNSET.fit(values, true, false).unwrap(), 0.0..4.0 * 1.05);
+        let bounds = Bounds {
+            min: Some(0.5),
+            max: None,
+        };
+        assert_eq!(bounds.fit(values, true, false).unwrap(), 0.5..4.0 * 1.05);
+        let bounds = Bounds {
+            min: None,
+            max: Some(0.5),
+        };
+        assert!(bounds.fit(values, false, false).is_err());
+    }
+
+    #[test]
+    fn fit_log() {
+        // points that cannot be shown are left out
+        let values = [0.0, -1.0, f64::NAN, 0.001, 0.01];
+        assert_eq!(UNSET.fit(values, false, true).unwrap(), 0.001..0.01);
+        assert_eq!(
+            UNSET.fit(values, true, true).unwrap(),
+            0.001 / 1.05..0.01 * 1.05
+        );
+        let bounds = Bounds {
+            min: Some(0.0),
+            max: None,
+        };
+        assert!(bounds.fit(values, false, true).is_err());
+    }
+
+    #[test]
+    fn fit_degenerate() {
+        assert_eq!(UNSET.fit([], false, false).unwrap(), 1.0..10.0);
+        assert_eq!(UNSET.fit([3.0], false, false).unwrap(), 1.5..4.5);
+        assert_eq!(UNSET.fit([0.0], false, false).unwrap(), 0.0..1.0);
+        assert_eq!(UNSET.fit([3.0], false, true).unwrap(), 1.5..6.0);
+    }
+}
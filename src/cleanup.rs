@@ -0,0 +1,68 @@
+use std::{
+    mem::take,
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+    thread::spawn,
+};
+
+use anyhow::Result;
+use nix::sys::signal::{SigSet, Signal};
+
+type Action = Box<dyn FnOnce() + Send>;
+
+/// Actions of the guards that are still alive, by guard
+static PENDING: Mutex<Vec<(usize, Action)>> = Mutex::new(Vec::new());
+
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+fn pending() -> MutexGuard<'static, Vec<(usize, Action)>> {
+    PENDING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Undoes a change to the system when dropped, or when the process is
+/// interrupted
+#[must_use]
+pub struct Guard(usize);
+
+impl Guard {
+    pub fn new(action: impl FnOnce() + Send + 'static) -> Self {
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        pending().push((id, Box::new(action)));
+        Self(id)
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let action = {
+            let mut pending = pending();
+            let i = pending.iter().position(|(id, _)| *id == self.0);
+            i.map(|i| pending.remove(i).1)
+        };
+        if let Some(action) = action {
+            action();
+        }
+    }
+}
+
+/// Runs the actions of every live guard, most recent first, and exits on
+/// SIGINT or SIGTERM. Must be called before any thread is spawned, so that
+/// they all leave the signals to the thread waiting for them.
+pub fn on_signal() -> Result<()> {
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGINT);
+    signals.add(Signal::SIGTERM);
+    signals.thread_block()?;
+    spawn(move || {
+        let signal = signals.wait().unwrap_or(Signal::SIGTERM);
+        let actions = take(&mut *pending());
+        for (_, action) in actions.into_iter().rev() {
+            action();
+        }
+        process::exit(128 + signal as i32);
+    });
+    Ok(())
+}
@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::{
+    manifest::QdiscStats,
+    methodology::{Counters, Impairment, Offloads},
+    runner::exec,
+};
+
+pub fn add_netns(netns: &str) -> Result<()> {
+    exec(format!("ip netns add {netns}"), None)?;
+    Ok(())
+}
+
+pub fn del_netns(netns: &str) -> Result<()> {
+    exec(format!("ip netns delete {netns}"), None)?;
+    Ok(())
+}
+
+/// Creates a veth pair with each end in its own namespace
+pub fn add_veth(end: &str, netns: &str, peer: &str, peer_netns: &str) -> Result<()> {
+    exec(
+        format!(
+            "ip link add dev {end} netns {netns} type veth peer name {peer} netns {peer_netns}"
+        ),
+        None,
+    )?;
+    Ok(())
+}
+
+/// Adds an IPv4 address, such as `10.1.1.1/24`
+pub fn add_address(netns: &str, end: &str, address: &str) -> Result<()> {
+    exec(format!("ip addr add dev {end} {address}"), Some(netns))?;
+    Ok(())
+}
+
+pub fn set_up(netns: &str, end: &str, mtu: u32) -> Result<()> {
+    exec(format!("ip link set dev {end} up mtu {mtu}"), Some(netns))?;
+    Ok(())
+}
+
+pub fn set_offloads(netns: &str, end: &str, offloads: &Offloads) -> Result<()> {
+    let mut args = String::new();
+    for (name, _, state) in offloads.features() {
+        if let Some(state) = state {
+            args += &format!(" {name} {}", if state { "on" } else { "off" });
+        }
+    }
+    if !args.is_empty() {
+        exec(format!("ethtool -K {end}{args}"), Some(netns))?;
+    }
+    Ok(())
+}
+
+/// Top level features of an end of the veth pair, as ethtool -k lists them
+pub fn features(netns: &str, end: &str) -> Result<BTreeMap<String, String>> {
+    let mut features = BTreeMap::new();
+    for line in exec(format!("ethtool -k {end}"), Some(netns))?.lines() {
+        if line.starts_with(char::is_whitespace) {
+            continue;
+        }
+        if let Some((name, state)) = line.split_once(": ") {
+            features.insert(name.into(), state.into());
+        }
+    }
+    Ok(features)
+}
+
+pub fn add_netem(netns: &str, end: &str, impairment: &Impairment) -> Result<()> {
+    let Impairment {
+        delay,
+        jitter,
+        loss,
+        correlation,
+    } = *impairment;
+    let mut args = format!("delay {delay}s");
+    if jitter > 0.0 {
+        args += &format!(" {jitter}s");
+    }
+    if loss > 0.0 {
+        args += &format!(" loss {loss}");
+        if correlation > 0.0 {
+            args += &format!(" {correlation}%");
+        }
+    }
+    exec(
+        format!("tc qdisc add dev {end} root netem {args}"),
+        Some(netns),
+    )?;
+    Ok(())
+}
+
+pub fn qdisc_stats(netns: &str, end: &str) -> Result<QdiscStats> {
+    let qdiscs: Vec<Value> = serde_json::from_str(&exec(
+        format!("tc -s -j qdisc show dev {end} root"),
+        Some(netns),
+    )?)
+    .with_context(|| format!("failed to parse the qdiscs of {end}"))?;
+    let qdisc = qdiscs
+        .into_iter()
+        .find(|e| e["root"] == true)
+        .with_context(|| format!("no root qdisc on {end}"))?;
+    Ok(serde_json::from_value(qdisc)?)
+}
+
+pub fn qdiscs(netns: &str) -> Result<String> {
+    exec("tc qdisc show", Some(netns))
+}
+
+pub fn del_qdisc(netns: &str, end: &str) -> Result<()> {
+    exec(format!("tc qdisc del dev {end} root"), Some(netns))?;
+    Ok(())
+}
+
+/// Adds the ip filter table
+pub fn add_table(netns: &str) -> Result<()> {
+    exec("nft add table ip filter", Some(netns))?;
+    Ok(())
+}
+
+/// Counts every packet arriving in the namespace, drops those larger than
+/// `mtu` if given and every `modulus`th of the others
+pub fn add_periodic_loss(netns: &str, mtu: Option<u32>, modulus: u32) -> Result<()> {
+    add_table(netns)?;
+    exec(
+        "nft add chain ip filter input { type filter hook input priority 0; }",
+        Some(netns),
+    )?;
+    exec("nft add rule filter input counter", Some(netns))?;
+    if let Some(mtu) = mtu {
+        exec(
+            format!("nft add rule filter input meta length > {mtu} counter drop"),
+            Some(netns),
+        )?;
+    }
+    exec(
+        format!(
+            "nft add rule filter input numgen inc mod {modulus} == {} counter drop",
+            modulus - 1,
+        ),
+        Some(netns),
+    )?;
+    Ok(())
+}
+
+pub fn periodic_loss_counters(netns: &str) -> Result<Counters> {
+    counters(&exec("nft -j list ruleset", Some(netns))?)
+}
+
+/// Counters of the rules of the filter table, told apart by what they match,
+/// in a ruleset listed by nft -j
+fn counters(ruleset: &str) -> Result<Counters> {
+    let ruleset: Value = serde_json::from_str(ruleset).context("failed to parse the ruleset")?;
+    let mut counters = Counters::default();
+    let rules = ruleset["nftables"].as_array().into_iter().flatten();
+    for rule in rules.filter_map(|e| e.get("rule")) {
+        if rule["table"] != "filter" || rule["chain"] != "input" {
+            continue;
+        }
+        let Some(expr) = rule["expr"].as_array() else {
+            continue;
+        };
+        let packets = expr.iter().find_map(|e| e["counter"]["packets"].as_u64());
+        let left = expr.iter().map(|e| &e["match"]["left"]);
+        if expr.len() == 1 {
+            counters.seen = packets;
+        } else if left.clone().any(|e| e.get("numgen").is_some()) {
+            counters.periodic = packets;
+        } else if left.clone().any(|e| e["meta"]["key"] == "length") {
+            counters.oversized = packets;
+        }
+    }
+    Ok(counters)
+}
+
+/// Counts the packets larger than `mtu` arriving in the namespace, in a table
+/// of its own hooked before the filter table so that none of its drops are
+/// missed
+pub fn add_oversized_counter(netns: &str, mtu: u32) -> Result<()> {
+    exec("nft add table ip check", Some(netns))?;
+    exec(
+        "nft add chain ip check input { type filter hook input priority -1; }",
+        Some(netns),
+    )?;
+    exec(
+        format!("nft add rule ip check input meta length > {mtu} counter"),
+        Some(netns),
+    )?;
+    Ok(())
+}
+
+pub fn oversized_counter(netns: &str) -> Result<Option<u64>> {
+    first_counter(&exec("nft -j list table ip check", Some(netns))?)
+}
+
+/// Packets counted by the first counter of a ruleset listed by nft -j
+fn first_counter(ruleset: &str) -> Result<Option<u64>> {
+    let ruleset: Value = serde_json::from_str(ruleset).context("failed to parse the ruleset")?;
+    let rules = ruleset["nftables"].as_array().into_iter().flatten();
+    Ok(rules
+        .filter_map(|e| e.get("rule"))
+        .flat_map(|e| e["expr"].as_array().into_iter().flatten())
+        .find_map(|e| e["counter"]["packets"].as_u64()))
+}
+
+pub fn del_oversized_counter(netns: &str) -> Result<()> {
+    exec("nft delete table ip check", Some(netns))?;
+    Ok(())
+}
+
+pub fn ruleset(netns: &str) -> Result<String> {
+    exec("nft list ruleset", Some(netns))
+}
+
+pub fn flush_ruleset(netns: &str) -> Result<()> {
+    exec("nft flush ruleset", Some(netns))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What nft -j list ruleset prints with periodic loss and the oversized
+    /// packet counter in place
+    const RULESET: &str = r#"{"nftables": [
+        {"metainfo": {"version": "1.0.6", "json_schema_version": 1}},
+        {"table": {"family": "ip", "name": "filter", "handle": 1}},
+        {"chain": {"family": "ip", "table": "filter", "name": "input", "handle": 1,
+            "type": "filter", "hook": "input", "prio": 0, "policy": "accept"}},
+        {"rule": {"family": "ip", "table": "filter", "chain": "input", "handle": 2,
+            "expr": [{"counter": {"packets": 1000, "bytes": 1560000}}]}},
+        {"rule": {"family": "ip", "table": "filter", "chain": "input", "handle": 3,
+            "expr": [
+                {"match": {"op": ">", "left": {"meta": {"key": "length"}}, "right": 1500}},
+                {"counter": {"packets": 2, "bytes": 6000}},
+                {"drop": null}]}},
+        {"rule": {"family": "ip", "table": "filter", "chain": "input", "handle": 4,
+            "expr": [
+                {"match": {"op": "==", "left": {"numgen": {"mode": "inc", "mod": 100,
+                    "offset": 0}}, "right": 99}},
+                {"counter": {"packets": 10, "bytes": 15600}},
+                {"drop": null}]}},
+        {"table": {"family": "ip", "name": "check", "handle": 2}},
+        {"chain": {"family": "ip", "table": "check", "name": "input", "handle": 1,
+            "type": "filter", "hook": "input", "prio": -1, "policy": "accept"}},
+        {"rule": {"family": "ip", "table": "check", "chain": "input", "handle": 2,
+            "expr": [
+                {"match": {"op": ">", "left": {"meta": {"key": "length"}}, "right": 1500}},
+                {"counter": {"packets": 3, "bytes": 9000}}]}}
+    ]}"#;
+
+    #[test]
+    fn periodic_loss() {
+        let counters = counters(RULESET).unwrap();
+        assert_eq!(counters.seen, Some(1000));
+        assert_eq!(counters.periodic, Some(10));
+        assert_eq!(counters.oversized, Some(2));
+    }
+
+    #[test]
+    fn without_rules() {
+        let counters = counters(r#"{"nftables": [{"metainfo": {}}]}"#).unwrap();
+        assert_eq!(counters.seen, None);
+        assert_eq!(counters.periodic, None);
+        assert_eq!(counters.oversized, None);
+        assert!(super::counters("table ip filter {}").is_err());
+    }
+
+    #[test]
+    fn oversized() {
+        // as nft -j list table ip check prints it
+        let table = r#"{"nftables": [
+            {"metainfo": {"version": "1.0.6", "json_schema_version": 1}},
+            {"table": {"family": "ip", "name": "check", "handle": 2}},
+            {"chain": {"family": "ip", "table": "check", "name": "input", "handle": 1,
+                "type": "filter", "hook": "input", "prio": -1, "policy": "accept"}},
+            {"rule": {"family": "ip", "table": "check", "chain": "input", "handle": 2,
+                "expr": [
+                    {"match": {"op": ">", "left": {"meta": {"key": "length"}}, "right": 1500}},
+                    {"counter": {"packets": 3, "bytes": 9000}}]}}
+        ]}"#;
+        assert_eq!(first_counter(table).unwrap(), Some(3));
+        assert_eq!(first_counter(r#"{"nftables": []}"#).unwrap(), None);
+    }
+}
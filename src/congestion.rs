@@ -0,0 +1,30 @@
+use std::{ffi::OsString, fs::read_to_string, net::TcpStream};
+
+use anyhow::{bail, Result};
+use nix::sys::socket::{setsockopt, sockopt::TcpCongestion};
+
+use crate::runner::{dry_run, exec};
+
+fn available() -> Result<Vec<String>> {
+    let available = read_to_string("/proc/sys/net/ipv4/tcp_available_congestion_control")?;
+    Ok(available.split_whitespace().map(Into::into).collect())
+}
+
+/// Makes sure the congestion control is available, loading its module if
+/// needed
+pub fn ensure(name: &str) -> Result<()> {
+    if available()?.iter().any(|e| e == name) {
+        return Ok(());
+    }
+    let _ = exec(format!("modprobe tcp_{name}"), None);
+    if !dry_run() && !available()?.iter().any(|e| e == name) {
+        bail!("congestion control {name} is not available");
+    }
+    Ok(())
+}
+
+/// Switches the congestion control of a single socket
+pub fn set(stream: &TcpStream, name: &str) -> Result<()> {
+    setsockopt(stream, TcpCongestion, &OsString::from(name))?;
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use std::fs::read_to_string;
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+
+use crate::{cleanup::Guard, runner::write_value};
+
+const PARAMETERS: &str = "/sys/module/tcp_cubic/parameters";
+
+/// Parameters of the tcp_cubic module, each left untouched if unset
+#[derive(Args, Clone, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cubic {
+    /// Turn on fast convergence (0 or 1)
+    #[arg(long = "cubic-fast-convergence")]
+    pub fast_convergence: Option<u32>,
+    /// Turn on TCP friendliness (0 or 1)
+    #[arg(long = "cubic-tcp-friendliness")]
+    pub tcp_friendliness: Option<u32>,
+    /// Multiplicative decrease factor, scaled by 1024
+    #[arg(long = "cubic-beta")]
+    pub beta: Option<u32>,
+    /// Scale of the cubic function, which sets C
+    #[arg(long = "cubic-bic-scale")]
+    pub bic_scale: Option<u32>,
+    /// Turn on hybrid slow start (0 or 1)
+    #[arg(long = "cubic-hystart")]
+    pub hystart: Option<u32>,
+    /// Hybrid slow start detection mechanisms (1 for ack train, 2 for delay,
+    /// 3 for both)
+    #[arg(long = "cubic-hystart-detect")]
+    pub hystart_detect: Option<u32>,
+    /// Lowest window at which hybrid slow start kicks in
+    #[arg(long = "cubic-hystart-low-window")]
+    pub hystart_low_window: Option<u32>,
+    /// Spacing between acks indicating a train, in microseconds
+    #[arg(long = "cubic-hystart-ack-delta-us")]
+    pub hystart_ack_delta_us: Option<u32>,
+}
+
+impl Cubic {
+    fn parameters(&self) -> [(&'static str, Option<u32>); 8] {
+        [
+            ("fast_convergence", self.fast_convergence),
+            ("tcp_friendliness", self.tcp_friendliness),
+            ("beta", self.beta),
+            ("bic_scale", self.bic_scale),
+            ("hystart", self.hystart),
+            ("hystart_detect", self.hystart_detect),
+            ("hystart_low_window", self.hystart_low_window),
+            ("hystart_ack_delta_us", self.hystart_ack_delta_us),
+        ]
+    }
+
+    pub fn check(&self) -> Result<()> {
+        for (name, value) in [
+            ("fast_convergence", self.fast_convergence),
+            ("tcp_friendliness", self.tcp_friendliness),
+            ("hystart", self.hystart),
+        ] {
+            if value.is_some_and(|value| value > 1) {
+                bail!("tcp_cubic {name} must be 0 or 1");
+            }
+        }
+        if self.beta.is_some_and(|beta| beta == 0 || beta >= 1024) {
+            bail!("tcp_cubic beta must be between 1 and 1023");
+        }
+        if self.hystart_detect.is_some_and(|detect| detect > 3) {
+            bail!("tcp_cubic hystart_detect must be between 0 and 3");
+        }
+        Ok(())
+    }
+
+    /// Overrides the parameters set in `other`
+    pub fn merge(&self, other: &Cubic) -> Cubic {
+        Cubic {
+            fast_convergence: other.fast_convergence.or(self.fast_convergence),
+            tcp_friendliness: other.tcp_friendliness.or(self.tcp_friendliness),
+            beta: other.beta.or(self.beta),
+            bic_scale: other.bic_scale.or(self.bic_scale),
+            hystart: other.hystart.or(self.hystart),
+            hystart_detect: other.hystart_detect.or(self.hystart_detect),
+            hystart_low_window: other.hystart_low_window.or(self.hystart_low_window),
+            hystart_ack_delta_us: other.hystart_ack_delta_us.or(self.hystart_ack_delta_us),
+        }
+    }
+
+    /// Names the parameters that are set, such as `beta-717`
+    pub fn label(&self) -> String {
+        let mut label = Vec::new();
+        for (name, value) in self.parameters() {
+            if let Some(value) = value {
+                label.push(format!("{name}-{value}"));
+            }
+        }
+        label.join("_")
+    }
+
+    /// Writes the parameters that are set, returning a guard that restores
+    /// their previous values when dropped
+    pub fn apply(&self) -> Result<Guard> {
+        let mut previous = Vec::new();
+        let applied = self.parameters().into_iter().try_for_each(|(name, value)| {
+            let Some(value) = value else {
+                return Ok(());
+            };
+            let path = format!("{PARAMETERS}/{name}");
+            let value_before = read_to_string(&path)
+                .with_context(|| format!("tcp_cubic {name} is not available"))?;
+            write_value(&path, value.to_string())
+                .with_context(|| format!("failed to set tcp_cubic {name}"))?;
+            previous.push((path, value_before.trim().to_string()));
+            anyhow::Ok(())
+        });
+        // restores whatever was written even if a later parameter failed
+        let guard = Guard::new(move || {
+            for (path, value) in previous.into_iter().rev() {
+                let _ = write_value(&path, value);
+            }
+        });
+        applied.map(|_| guard)
+    }
+}
@@ -0,0 +1,315 @@
+use std::{fs::read_to_string, path::Path, time::Duration};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::{
+    cleanup::Guard,
+    cubic::Cubic,
+    manifest::Drops,
+    methodology::{check_grid, Impairment, Methodology, Offloads, Sender, Topology},
+    tcp_info,
+};
+
+/// An experiment described by a TOML file rather than a hard-coded
+/// methodology
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Experiment {
+    #[serde(default)]
+    pub topology: Topology,
+    #[serde(default)]
+    pub offloads: Offloads,
+    #[serde(default)]
+    pub delay: Delay,
+    #[serde(default)]
+    pub cubic: Cubic,
+    pub loss: Loss,
+    /// Congestion control of the sending socket, the system default if unset
+    pub congestion: Option<String>,
+    /// Duration of a single run, in seconds
+    #[serde(default = "default_duration")]
+    pub duration: u64,
+    /// Interval between two measurements, in seconds
+    #[serde(default = "default_interval")]
+    pub interval: f64,
+    /// tcp_info fields to record alongside the congestion window
+    #[serde(default = "default_fields")]
+    pub fields: Vec<String>,
+    /// Trace the congestion window on every ACK with tcp_probe
+    #[serde(default)]
+    pub probe: bool,
+    pub sweep: Sweep,
+}
+
+fn default_duration() -> u64 {
+    60
+}
+
+fn default_interval() -> f64 {
+    0.1
+}
+
+fn default_fields() -> Vec<String> {
+    ["rtt", "snd_ssthresh", "total_retrans", "delivery_rate"]
+        .map(Into::into)
+        .into()
+}
+
+/// Delay added by netem on top of the swept RTT
+#[derive(Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Delay {
+    /// Jitter, in seconds
+    #[serde(default)]
+    pub jitter: f64,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "model", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Loss {
+    /// Random loss with tc-netem
+    Random {
+        /// Correlation with the previous loss event, in percent
+        #[serde(default)]
+        correlation: f64,
+        /// Give netem the rate as its percentage, so that it drops a
+        /// hundredth of it, as out.old and out.im2 were produced
+        #[serde(default)]
+        percentage: bool,
+    },
+    /// Periodic loss with nftables
+    Periodic {
+        /// Drop packets larger than the MTU as well, which only TSO or GRO
+        /// produce
+        #[serde(default = "default_oversized")]
+        oversized: bool,
+    },
+}
+
+fn default_oversized() -> bool {
+    true
+}
+
+/// Parameter grid, every combination of which is a single run
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sweep {
+    /// Round trip times, in seconds
+    pub rtt: Vec<f64>,
+    /// Packet loss rates
+    pub loss: Vec<f64>,
+    /// tcp_cubic parameters, each overriding those of the experiment
+    #[serde(default)]
+    pub cubic: Vec<Cubic>,
+    /// Congestion controls, each overriding that of the experiment
+    #[serde(default)]
+    pub congestion: Vec<String>,
+}
+
+/// Settings of the sender that may differ between the sweeps of an experiment
+#[derive(Clone, Default)]
+pub struct Variant {
+    pub cubic: Cubic,
+    pub congestion: Option<String>,
+}
+
+impl Variant {
+    pub fn check(&self) -> Result<()> {
+        self.cubic.check()?;
+        if let Some(congestion) = &self.congestion {
+            if congestion.is_empty() || !congestion.chars().all(|c| c.is_ascii_alphanumeric()) {
+                bail!("congestion control {congestion:?} is not a valid name");
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends for `duration` seconds, measuring every `interval` seconds
+    pub fn sender(
+        &self,
+        duration: u64,
+        interval: f64,
+        fields: &[String],
+        probe: bool,
+    ) -> Result<Sender> {
+        // the throughput is averaged between two measurements at least
+        if !(interval > 0.0 && interval * 2.0 <= duration as f64) {
+            bail!("interval must be positive and at most half the duration");
+        }
+        Ok(Sender {
+            congestion: self.congestion.clone(),
+            duration: Duration::from_secs(duration),
+            interval: Duration::from_secs_f64(interval),
+            fields: tcp_info::fields(fields)?,
+            probe,
+        })
+    }
+
+    /// Names the settings, such as `bbr` or `cubic_beta-717`
+    pub fn label(&self) -> String {
+        let mut label = self.congestion.clone().unwrap_or_default();
+        let cubic = self.cubic.label();
+        if !label.is_empty() && !cubic.is_empty() {
+            label += "_";
+        }
+        label + &cubic
+    }
+}
+
+impl Experiment {
+    pub fn load(path: &Path) -> Result<Self> {
+        let experiment: Self = toml::from_str(&read_to_string(path)?)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        experiment
+            .check()
+            .with_context(|| format!("invalid experiment {}", path.display()))?;
+        Ok(experiment)
+    }
+
+    fn check(&self) -> Result<()> {
+        let Topology {
+            server,
+            client,
+            mtu,
+            ..
+        } = &self.topology;
+        for name in [server, client] {
+            if name.is_empty() || name.len() > 15 || name.contains(char::is_whitespace) {
+                bail!("{name:?} is not a valid interface name");
+            }
+        }
+        if server == client {
+            bail!("server and client ends must be named differently");
+        }
+        if !(68..=65535).contains(mtu) {
+            bail!("mtu {mtu} is out of range");
+        }
+        if self.delay.jitter.is_nan() || self.delay.jitter < 0.0 {
+            bail!("jitter must not be negative");
+        }
+        if let Loss::Random { correlation, .. } = self.loss {
+            if !(0.0..=100.0).contains(&correlation) {
+                bail!("correlation {correlation} is not a percentage");
+            }
+        }
+        if self.duration == 0 {
+            bail!("duration must not be zero");
+        }
+        let variants = self.variants();
+        let mut labels = Vec::new();
+        for variant in &variants {
+            variant.check()?;
+            self.sender(variant)?;
+            let label = variant.label();
+            if variants.len() > 1 && labels.contains(&label) {
+                bail!("sender settings {label:?} are swept more than once");
+            }
+            labels.push(label);
+        }
+        let periodic = matches!(self.loss, Loss::Periodic { .. });
+        check_grid(&self.sweep.rtt, &self.sweep.loss, periodic)
+    }
+
+    /// How a variant sends during every run
+    pub fn sender(&self, variant: &Variant) -> Result<Sender> {
+        variant.sender(self.duration, self.interval, &self.fields, self.probe)
+    }
+
+    /// The sender settings of every sweep, a single one if they are not
+    /// swept
+    pub fn variants(&self) -> Vec<Variant> {
+        let cubics = match self.sweep.cubic.is_empty() {
+            true => vec![self.cubic.clone()],
+            false => self
+                .sweep
+                .cubic
+                .iter()
+                .map(|e| self.cubic.merge(e))
+                .collect(),
+        };
+        let congestions = match self.sweep.congestion.is_empty() {
+            true => vec![self.congestion.clone()],
+            false => self.sweep.congestion.iter().cloned().map(Some).collect(),
+        };
+        let mut variants = Vec::new();
+        for congestion in &congestions {
+            for cubic in &cubics {
+                variants.push(Variant {
+                    cubic: cubic.clone(),
+                    congestion: congestion.clone(),
+                });
+            }
+        }
+        variants
+    }
+}
+
+impl Methodology for Experiment {
+    fn name(&self) -> &str {
+        "experiment"
+    }
+
+    fn settings(&self) -> Value {
+        json!({
+            "offloads": self.offloads,
+            "delay": self.delay,
+            "loss": self.loss,
+            // rate netem drops at for a nominal rate of 1, which tells runs
+            // apart from those that gave netem the rate as a percentage
+            "random_loss": self.random_loss(1.0),
+        })
+    }
+
+    fn offloads(&self) -> Offloads {
+        self.offloads
+    }
+
+    fn setup(&self, topology: &Topology) -> Result<Guard> {
+        let guard = topology.create()?;
+        topology.set_offloads(&self.offloads)?;
+        Ok(guard)
+    }
+
+    fn impair(&self, topology: &Topology, r: f64, p: f64) -> Result<()> {
+        let mut impairment = Impairment {
+            delay: r,
+            jitter: self.delay.jitter,
+            ..Default::default()
+        };
+        match self.loss {
+            Loss::Random {
+                correlation,
+                percentage,
+            } => {
+                impairment.loss = if percentage { p } else { p * 100.0 };
+                impairment.correlation = correlation;
+            }
+            Loss::Periodic { oversized } => topology.add_periodic_loss(p, oversized)?,
+        }
+        topology.add_netem(&impairment)
+    }
+
+    fn random_loss(&self, p: f64) -> Option<f64> {
+        match self.loss {
+            Loss::Random { percentage, .. } => Some(if percentage { p / 100.0 } else { p }),
+            Loss::Periodic { .. } => None,
+        }
+    }
+
+    fn drops(&self, topology: &Topology, p: f64) -> Result<Option<Drops>> {
+        match self.loss {
+            Loss::Random { .. } => Ok(None),
+            Loss::Periodic { oversized } => topology.read_periodic_loss(p, oversized).map(Some),
+        }
+    }
+
+    fn teardown(&self, topology: &Topology, _r: f64, _p: f64) -> Result<()> {
+        topology.del_netem()?;
+        if let Loss::Periodic { .. } = self.loss {
+            topology.del_periodic_loss()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::Result;
+use serde_json::{json, Value};
+
+use crate::{
+    cleanup::Guard,
+    manifest::Drops,
+    methodology::{Impairment, Methodology, Offloads, Topology},
+};
+
+/// TSO left enabled, constant delay with tc-netem and periodic loss with
+/// nftables, which produced out.im1
+pub struct Intermediary1;
+
+impl Methodology for Intermediary1 {
+    fn name(&self) -> &str {
+        "intermediary1"
+    }
+
+    fn settings(&self) -> Value {
+        // tells runs apart from those that also dropped GSO packets
+        json!({ "oversized": "kept" })
+    }
+
+    /// Drops periodically without the oversized rule of the revised
+    /// methodology, which would drop every GSO packet with TSO left on
+    fn impair(&self, topology: &Topology, r: f64, p: f64) -> Result<()> {
+        topology.add_periodic_loss(p, false)?;
+        topology.add_netem(&Impairment {
+            delay: r,
+            ..Default::default()
+        })
+    }
+
+    fn drops(&self, topology: &Topology, p: f64) -> Result<Option<Drops>> {
+        topology.read_periodic_loss(p, false).map(Some)
+    }
+
+    fn teardown(&self, topology: &Topology, _r: f64, _p: f64) -> Result<()> {
+        topology.del_netem()?;
+        topology.del_periodic_loss()
+    }
+}
+
+/// TSO disabled, constant delay and random loss with tc-netem, which produced
+/// out.im2
+pub struct Intermediary2;
+
+impl Methodology for Intermediary2 {
+    fn name(&self) -> &str {
+        "intermediary2"
+    }
+
+    fn offloads(&self) -> Offloads {
+        Offloads {
+            tso: Some(false),
+            ..Default::default()
+        }
+    }
+
+    fn setup(&self, topology: &Topology) -> Result<Guard> {
+        let guard = topology.create()?;
+        topology.disable_tso()?;
+        Ok(guard)
+    }
+
+    /// Gives netem the loss rate as a percentage, as out.im2 was produced,
+    /// which has it drop a hundredth of the nominal rate
+    fn random_loss(&self, p: f64) -> Option<f64> {
+        Some(p / 100.0)
+    }
+
+    fn impair(&self, topology: &Topology, r: f64, p: f64) -> Result<()> {
+        topology.add_netem(&Impairment {
+            delay: r,
+            loss: p,
+            ..Default::default()
+        })
+    }
+
+    fn teardown(&self, topology: &Topology, _r: f64, _p: f64) -> Result<()> {
+        topology.del_netem()
+    }
+}
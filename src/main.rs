@@ -1,249 +1,735 @@
-use anyhow::{Context, Error, Result};
+use anyhow::{bail, Context, Result};
+use backend::Backend;
+use cache::{lookup, select, store, Config};
+use chart::{compare_summary, compare_traces, run_chart, summary_chart, Style};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use csv::{Reader, StringRecord, Writer};
-use initial::{init_old, simulate_old};
-use nix::libc::{getsockopt, SOL_TCP, TCP_INFO};
-use plotters::prelude::*;
-use revised::{init_new, simulate_new};
+use cubic::Cubic;
+use experiment::{Experiment, Variant};
+use initial::Initial;
+use intermediary::{Intermediary1, Intermediary2};
+use methodology::{check_grid, plan, prepare, simulate, Methodology, Sender, Topology};
+use model::Models;
+use revised::Revised;
+use scheduler::Jobs;
 use std::{
-    mem::{size_of, MaybeUninit},
-    net::TcpStream,
-    os::fd::AsRawFd,
-    process::Command,
+    fs::{create_dir_all, read_dir},
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
+mod backend;
+mod cache;
+mod chart;
+mod cleanup;
+mod command;
+mod congestion;
+mod cubic;
+mod experiment;
 mod initial;
+mod intermediary;
+mod manifest;
+mod methodology;
+mod model;
+mod netlink;
+mod probe;
+mod report;
 mod revised;
+mod rootless;
+mod runner;
+mod scheduler;
+mod tcp_info;
 
-pub fn exec(command: impl AsRef<str>, netns: Option<&str>) -> Result<String> {
-    let mut command = command.as_ref().split_whitespace();
-    let output = if let Some(netns) = netns {
-        Command::new("ip")
-            .args(["netns", "exec", netns])
-            .args(command)
-            .output()?
-    } else {
-        Command::new(command.next().unwrap())
-            .args(command)
-            .output()?
-    };
-    if output.status.success() {
-        Ok(String::from_utf8(output.stdout)?)
-    } else {
-        Err(Error::msg(String::from_utf8(output.stderr)?))
-    }
-}
-
-#[repr(C)]
-struct TcpInfo {
-    pub tcpi_ca_state: u8,
-    pub tcpi_state: u8,
-    pub tcpi_retransmits: u8,
-    pub tcpi_probes: u8,
-    pub tcpi_backoff: u8,
-    pub tcpi_options: u8,
-    pub tcpi_snd_wscale: u8,
-    pub tcpi_rcv_wscale: u8,
-    pub tcpi_rto: u32,
-    pub tcpi_ato: u32,
-    pub tcpi_snd_mss: u32,
-    pub tcpi_rcv_mss: u32,
-    pub tcpi_unacked: u32,
-    pub tcpi_sacked: u32,
-    pub tcpi_lost: u32,
-    pub tcpi_retrans: u32,
-    pub tcpi_fackets: u32,
-    pub tcpi_last_data_sent: u32,
-    pub tcpi_last_ack_sent: u32,
-    pub tcpi_last_data_recv: u32,
-    pub tcpi_last_ack_recv: u32,
-    pub tcpi_pmtu: u32,
-    pub tcpi_rcv_ssthresh: u32,
-    pub tcpi_rtt: u32,
-    pub tcpi_rttvar: u32,
-    pub tcpi_snd_ssthresh: u32,
-    pub tcpi_snd_cwnd: u32,
-    pub tcpi_advmss: u32,
-    pub tcpi_reordering: u32,
-    pub tcpi_rcv_rtt: u32,
-    pub tcpi_rcv_space: u32,
-    pub tcpi_total_retrans: u32,
-}
-
-fn tcp_info(stream: &TcpStream) -> Result<TcpInfo> {
-    unsafe {
-        let mut tcp_info = MaybeUninit::<TcpInfo>::uninit();
-        let mut sock_len = size_of::<TcpInfo>() as u32;
-        let ret = getsockopt(
-            stream.as_raw_fd(),
-            SOL_TCP,
-            TCP_INFO,
-            tcp_info.as_mut_ptr().cast(),
-            &mut sock_len,
-        );
-        if ret != 0 {
-            Err(nix::Error::last().into())
-        } else {
-            Ok(tcp_info.assume_init())
-        }
-    }
-}
-
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Measurement {
+    /// Time since the start of the run, in seconds
+    time: f64,
     bytes_transferred: usize,
     congestion_window: usize,
+    /// Additional tcp_info fields, in the order they were requested
+    fields: Vec<(&'static str, u64)>,
 }
 
-fn estimate(r: f64, p: f64) -> f64 {
-    const C: f64 = 0.4;
-    f64::max(
-        (C * 3.7 / 1.2).powf(0.25) * (r / p).powf(0.75),
-        (3.0 / 2.0 / p).powf(0.5),
-    )
-}
-
-fn plot(r: f64, p: f64, measurements: &[Measurement]) -> Result<()> {
-    let path = format!("out/{r}_{:.5}.png", p);
-    let root = BitMapBackend::new(&path, (1440, 720)).into_drawing_area();
-    root.fill(&WHITE)?;
-    let mut chart = ChartBuilder::on(&root)
-        .caption(
-            format!("CWND (Packet) vs Time (RTT) (r = {r}, p = {:.5})", p),
-            ("sans-serif", 32),
-        )
-        .x_label_area_size(64)
-        .y_label_area_size(96)
-        .margin_right(32)
-        .build_cartesian_2d(0..measurements.len(), 0..4096usize)?;
-    chart
-        .configure_mesh()
-        .x_desc("Time (RTT)")
-        .y_desc("CWND (Packet)")
-        .label_style(("sans-serif", 24))
-        .draw()?;
-    chart
-        .draw_series(LineSeries::new(
-            measurements.iter().map(|e| e.congestion_window).enumerate(),
-            RED,
-        ))?
-        .label("Simulated")
-        .legend(|(x, y)| PathElement::new([(x, y), (x + 24, y)], RED));
-    let estimation = estimate(r, p) as usize;
-    chart
-        .draw_series(LineSeries::new(
-            [(0, estimation), (measurements.len(), estimation)],
-            BLUE,
-        ))?
-        .label("Estimated")
-        .legend(|(x, y)| PathElement::new([(x, y), (x + 24, y)], BLUE));
-    chart
-        .configure_series_labels()
-        .label_font(("sans-serif", 24))
-        .border_style(BLACK)
-        .draw()?;
-    root.present()?;
-    Ok(())
+/// Interval between two measurements in CSVs that predate timestamps
+const LEGACY_INTERVAL: f64 = 0.1;
+
+/// Throughputs (MB/s) by loss rate, grouped by RTT
+type Summary = Vec<(f64, Vec<(f64, f64)>)>;
+
+/// Average throughput over the second half of a run, in MB/s
+fn throughput(measurements: &[Measurement]) -> Result<f64> {
+    if measurements.len() < 2 {
+        bail!(
+            "{} measurements are too few to average the throughput over",
+            measurements.len()
+        );
+    }
+    let a = &measurements[measurements.len() / 2 - 1];
+    let b = &measurements[measurements.len() - 1];
+    Ok((b.bytes_transferred - a.bytes_transferred) as f64
+        / (b.time - a.time)
+        / 1024f64 //  B/s -> KB/s
+        / 1024f64) // KB/s -> MB/s
 }
 
-fn save(r: f64, p: f64, measurements: &[Measurement]) -> Result<()> {
-    let mut writer = Writer::from_path(format!("out/{r}_{:.5}.csv", p))?;
-    writer.write_record(["bytes_transferred", "congestion_window"])?;
+fn save(out: &Path, r: f64, p: f64, measurements: &[Measurement]) -> Result<()> {
+    let mut writer = Writer::from_path(out.join(format!("{r}_{:.5}.csv", p)))?;
+    let mut header = vec!["time", "bytes_transferred", "congestion_window"];
+    if let Some(measurement) = measurements.first() {
+        header.extend(measurement.fields.iter().map(|(name, _)| *name));
+    }
+    writer.write_record(header)?;
     for measurement in measurements {
-        writer.write_record([
+        let mut record = vec![
+            measurement.time.to_string(),
             measurement.bytes_transferred.to_string(),
             measurement.congestion_window.to_string(),
-        ])?;
+        ];
+        record.extend(
+            measurement
+                .fields
+                .iter()
+                .map(|(_, value)| value.to_string()),
+        );
+        writer.write_record(record)?;
     }
     writer.flush()?;
     Ok(())
 }
 
-fn load(r: f64, p: f64) -> Result<Vec<Measurement>> {
+fn load(out: &Path, r: f64, p: f64) -> Result<Vec<Measurement>> {
+    read(&out.join(format!("{r}_{:.5}.csv", p)))
+}
+
+fn read(path: &Path) -> Result<Vec<Measurement>> {
     let mut measurements = Vec::new();
-    let mut reader = Reader::from_path(format!("out/{r}_{:.5}.csv", p))?;
+    let mut reader = Reader::from_path(path)?;
+    let headers = reader.headers()?.clone();
+    let column = |name| headers.iter().position(|e| e == name);
+    let time = column("time");
+    let bytes_transferred = column("bytes_transferred").context("no bytes_transferred column")?;
+    let congestion_window = column("congestion_window").context("no congestion_window column")?;
+    let fields = tcp_info::FIELDS
+        .iter()
+        .filter_map(|name| Some((*name, column(name)?)))
+        .collect::<Vec<_>>();
     let mut record = StringRecord::new();
     while reader.read_record(&mut record)? {
+        let field = |i| record.get(i).context("not enough items in record");
         measurements.push(Measurement {
-            bytes_transferred: record
-                .get(0)
-                .context("not enough items in record")?
-                .parse()?,
-            congestion_window: record
-                .get(1)
-                .context("not enough items in record")?
-                .parse()?,
+            time: match time {
+                Some(time) => field(time)?.parse()?,
+                None => measurements.len() as f64 * LEGACY_INTERVAL,
+            },
+            bytes_transferred: field(bytes_transferred)?.parse()?,
+            congestion_window: field(congestion_window)?.parse()?,
+            fields: fields
+                .iter()
+                .map(|(name, i)| Ok((*name, field(*i)?.parse()?)))
+                .collect::<Result<_>>()?,
         })
     }
     Ok(measurements)
 }
 
-fn main() {
-    init_old().unwrap(); // change to old as needed
-    let throughputs = (1..10).map(|i| {
-        let r = 10f64.powf(-1.0);
-        let p = 10f64.powf(-5.0) * i as f64;
-        let measurements = load(r, p).unwrap_or_else(|_| {
-            let measurements = simulate_old(r, p).unwrap(); // change to old as needed
-            save(r, p, &measurements).unwrap();
-            measurements
-        });
-        plot(r, p, &measurements).unwrap();
-        let a = measurements[measurements.len() / 2 - 1];
-        let b = measurements[measurements.len() - 1];
-        ((b.bytes_transferred - a.bytes_transferred) * 2) as f64
-            / measurements.len() as f64
-            / 0.1
-            / 1024f64 //  B/s -> KB/s
-            / 1024f64 // KB/s -> MB/s
-    });
-    let root = BitMapBackend::new("out/main.png", (1440, 720)).into_drawing_area();
-    root.fill(&WHITE).unwrap();
-    let mut chart = ChartBuilder::on(&root)
-        .caption(
-            format!("Average Throughput (MB/s) vs Packet Loss Rate (%) (r = 0.1)"),
-            ("sans-serif", 32),
-        )
-        .x_label_area_size(64)
-        .y_label_area_size(64)
-        .margin_right(32)
-        .build_cartesian_2d(0.00001..0.00009, 0.0..20.0)
-        .unwrap();
-    chart
-        .configure_mesh()
-        .x_desc("Packet Loss Rate (%)")
-        .y_desc("Average Throuhgput(MB/s)")
-        .label_style(("sans-serif", 24))
-        .x_label_formatter(&|x: &f64| format!("{:.3}", x * 100.0))
-        .y_label_formatter(&|y: &f64| format!("{:.0}", y))
-        .draw()
-        .unwrap();
-    chart
-        .draw_series(LineSeries::new(
-            throughputs
-                .into_iter()
-                .enumerate()
-                .map(|(i, e)| (0.00001 * (i + 1) as f64, e)),
-            RED,
-        ))
-        .unwrap()
-        .label("Simulated")
-        .legend(|(x, y)| PathElement::new([(x, y), (x + 24, y)], RED));
-    chart
-        .draw_series(LineSeries::new(
-            (1..10).map(|i| {
-                let i = 0.00001 * i as f64;
-                (i, estimate(0.1, i) / 0.1 * 1560.0 / 1024.0 / 1024.0)
-            }),
-            BLUE,
-        ))
-        .unwrap()
-        .label("Estimated")
-        .legend(|(x, y)| PathElement::new([(x, y), (x + 24, y)], BLUE));
-    chart
-        .configure_series_labels()
-        .label_font(("sans-serif", 24))
-        .border_style(BLACK)
-        .draw()
-        .unwrap();
-    root.present().unwrap();
+#[derive(Clone, Copy, ValueEnum)]
+enum Method {
+    /// TSO left enabled, random loss with tc-netem
+    #[value(alias = "initial")]
+    Old,
+    /// TSO left enabled, periodic loss with nftables
+    #[value(alias = "intermediary1")]
+    Im1,
+    /// TSO disabled, random loss with tc-netem
+    #[value(alias = "intermediary2")]
+    Im2,
+    /// TSO disabled, periodic loss with nftables
+    #[value(alias = "revised")]
+    New,
+}
+
+impl Method {
+    fn methodology(self) -> &'static dyn Methodology {
+        match self {
+            Method::Old => &Initial,
+            Method::Im1 => &Intermediary1,
+            Method::Im2 => &Intermediary2,
+            Method::New => &Revised,
+        }
+    }
+}
+
+#[derive(Args)]
+struct Grid {
+    /// Round trip times to sweep, in seconds
+    #[arg(long, value_delimiter = ',', default_value = "0.1")]
+    rtt: Vec<f64>,
+    /// Packet loss rates to sweep
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "0.00001,0.00002,0.00003,0.00004,0.00005,0.00006,0.00007,0.00008,0.00009"
+    )]
+    loss: Vec<f64>,
+    /// Directory the results are read from and written to
+    #[arg(long, default_value = "out")]
+    out: PathBuf,
+    /// Loss rate the throughputs are plotted and modelled against
+    #[arg(long, value_enum, default_value_t = LossAxis::Nominal)]
+    loss_axis: LossAxis,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LossAxis {
+    /// Loss rate of the grid
+    Nominal,
+    /// Loss rate measured over each run, from nftables or netem counters
+    Measured,
+}
+
+impl Grid {
+    /// Every run found in a result directory
+    fn discover(out: &Path, loss_axis: LossAxis) -> Result<Self> {
+        let (mut rtt, mut loss) = (Vec::new(), Vec::new());
+        let entries = read_dir(out).with_context(|| format!("failed to read {}", out.display()))?;
+        for entry in entries {
+            let name = entry?.file_name();
+            let Some((r, p)) = name
+                .to_str()
+                .and_then(|e| e.strip_suffix(".csv")?.split_once('_'))
+            else {
+                continue;
+            };
+            // skips the traces of tcp_probe, among others
+            if let (Ok(r), Ok(p)) = (r.parse(), p.parse()) {
+                rtt.push(r);
+                loss.push(p);
+            }
+        }
+        for values in [&mut rtt, &mut loss] {
+            values.sort_by(f64::total_cmp);
+            values.dedup();
+        }
+        Ok(Self {
+            rtt,
+            loss,
+            out: out.into(),
+            loss_axis,
+        })
+    }
+
+    fn points(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        self.rtt
+            .iter()
+            .flat_map(|r| self.loss.iter().map(move |p| (*r, *p)))
+    }
+
+    /// Points of the grid that have a run in the result directory
+    fn runs(&self) -> impl Iterator<Item = (f64, f64)> + '_ {
+        self.points()
+            .filter(|(r, p)| self.out.join(format!("{r}_{:.5}.csv", p)).exists())
+    }
+}
+
+/// Result directories to replay, which needs no privileges
+#[derive(Args)]
+struct Results {
+    /// Directories the results are read from, as a comma separated list
+    #[arg(long, value_delimiter = ',', default_value = "out")]
+    out: Vec<PathBuf>,
+    /// Round trip times to read, every one found if unset
+    #[arg(long, value_delimiter = ',')]
+    rtt: Vec<f64>,
+    /// Packet loss rates to read, every one found if unset
+    #[arg(long, value_delimiter = ',')]
+    loss: Vec<f64>,
+    /// Loss rate the throughputs are plotted and modelled against
+    #[arg(long, value_enum, default_value_t = LossAxis::Nominal)]
+    loss_axis: LossAxis,
+    /// Directory the charts are written to. `plot` and `report` write into
+    /// the result directory itself if unset, and into a subdirectory per result directory
+    /// when reading several; `compare` writes into `compare` if unset.
+    #[arg(long)]
+    charts: Option<PathBuf>,
+}
+
+impl Results {
+    /// A grid per result directory, covering the runs found in it
+    fn grids(&self) -> Result<Vec<Grid>> {
+        let mut grids = Vec::new();
+        for out in &self.out {
+            let mut grid = Grid::discover(out, self.loss_axis)?;
+            if !self.rtt.is_empty() {
+                grid.rtt = self.rtt.clone();
+            }
+            if !self.loss.is_empty() {
+                grid.loss = self.loss.clone();
+            }
+            if grid.runs().next().is_none() {
+                bail!("no runs in {}", out.display());
+            }
+            grids.push(grid);
+        }
+        Ok(grids)
+    }
+
+    /// Directory the charts of a result directory are written to
+    fn charts(&self, out: &Path) -> PathBuf {
+        match &self.charts {
+            None => out.into(),
+            Some(charts) if self.out.len() == 1 => charts.clone(),
+            Some(charts) => charts.join(name(out)),
+        }
+    }
+}
+
+/// Names a result directory after its last component
+fn name(out: &Path) -> String {
+    let name = out.file_name().unwrap_or(out.as_os_str());
+    name.to_string_lossy().into_owned()
+}
+
+#[derive(Args)]
+struct Simulation {
+    /// Methodology used to set up the link and inject loss
+    #[arg(long, value_enum, default_value_t = Method::Old)]
+    methodology: Method,
+    /// Duration of a single run, in seconds
+    #[arg(long, default_value_t = 60)]
+    duration: u64,
+    /// Interval between two measurements, in seconds
+    #[arg(long, default_value_t = 0.1)]
+    interval: f64,
+    /// Congestion control of the sending socket, the system default if unset
+    #[arg(long)]
+    congestion: Option<String>,
+    /// tcp_info fields to record alongside the congestion window, as a comma
+    /// separated list
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "rtt,snd_ssthresh,total_retrans,delivery_rate"
+    )]
+    fields: Vec<String>,
+    /// Trace the congestion window on every ACK with the tcp_probe tracepoint
+    #[arg(long)]
+    probe: bool,
+    #[command(flatten, next_help_heading = "tcp_cubic parameters")]
+    cubic: Cubic,
+}
+
+impl Simulation {
+    fn check(&self, rtt: &[f64], loss: &[f64]) -> Result<()> {
+        // methodologies that do not drop at random drop periodically
+        let periodic = self.methodology.methodology().random_loss(1.0).is_none();
+        check_grid(rtt, loss, periodic)
+    }
+
+    fn variant(&self) -> Result<Variant> {
+        let variant = Variant {
+            cubic: self.cubic.clone(),
+            congestion: self.congestion.clone(),
+        };
+        variant.check()?;
+        Ok(variant)
+    }
+
+    fn sender(&self, variant: &Variant) -> Result<Sender> {
+        variant.sender(self.duration, self.interval, &self.fields, self.probe)
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Simulates a single run, ignoring any cached result
+    Run {
+        /// Round trip time, in seconds
+        #[arg(long, default_value_t = 0.1)]
+        rtt: f64,
+        /// Packet loss rate
+        #[arg(long)]
+        loss: f64,
+        /// Directory the results are written to
+        #[arg(long, default_value = "out")]
+        out: PathBuf,
+        #[command(flatten)]
+        simulation: Simulation,
+        #[command(flatten, next_help_heading = "Models")]
+        models: Models,
+        #[command(flatten, next_help_heading = "Charts")]
+        style: Style,
+    },
+    /// Simulates every missing run of the grid and plots the results
+    Sweep {
+        #[command(flatten)]
+        grid: Grid,
+        #[command(flatten)]
+        simulation: Simulation,
+        #[command(flatten, next_help_heading = "Models")]
+        models: Models,
+        #[command(flatten, next_help_heading = "Charts")]
+        style: Style,
+        #[command(flatten, next_help_heading = "Scheduling")]
+        jobs: Jobs,
+    },
+    /// Checks an experiment file and simulates every missing run of its sweep
+    Experiment {
+        /// TOML file describing the experiment
+        file: PathBuf,
+        /// Only check the file and list the runs it expands to
+        #[arg(long)]
+        check: bool,
+        /// Directory the results are read from and written to
+        #[arg(long, default_value = "out")]
+        out: PathBuf,
+        #[command(flatten, next_help_heading = "Models")]
+        models: Models,
+        #[command(flatten, next_help_heading = "Charts")]
+        style: Style,
+        #[command(flatten, next_help_heading = "Scheduling")]
+        jobs: Jobs,
+    },
+    /// Plots the runs found in result directories and their throughput
+    /// summary
+    Plot {
+        #[command(flatten)]
+        results: Results,
+        #[command(flatten, next_help_heading = "Models")]
+        models: Models,
+        #[command(flatten, next_help_heading = "Charts")]
+        style: Style,
+    },
+    /// Overlays the throughput summaries of several result directories, and
+    /// stacks the congestion windows of the runs they have in common
+    Compare {
+        #[command(flatten)]
+        results: Results,
+        /// Names of the result directories in legends, as a comma separated
+        /// list, their file names if unset
+        #[arg(long, value_delimiter = ',')]
+        labels: Vec<String>,
+        #[command(flatten, next_help_heading = "Models")]
+        models: Models,
+        #[command(flatten, next_help_heading = "Charts")]
+        style: Style,
+    },
+    /// Writes a Markdown and an HTML report on each result directory, with
+    /// its methodology, manifests, charts, throughputs against the models and
+    /// the warnings its runs raised
+    Report {
+        #[command(flatten)]
+        results: Results,
+        #[command(flatten, next_help_heading = "Models")]
+        models: Models,
+        #[command(flatten, next_help_heading = "Charts")]
+        style: Style,
+    },
+    /// Prints the average throughput of the runs found in result directories
+    /// against the models
+    Analyze {
+        #[command(flatten)]
+        results: Results,
+        #[command(flatten, next_help_heading = "Models")]
+        models: Models,
+    },
+}
+
+#[derive(Parser)]
+#[command(about = "Compares simulated TCP throughput against analytical models")]
+struct Cli {
+    /// Build the topology in private user and network namespaces, which does
+    /// not require root where the kernel allows unprivileged user namespaces
+    #[arg(long, global = true)]
+    rootless: bool,
+    /// Print the commands that would set up and tear down the topology as a
+    /// shell script instead of running anything
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Seconds after which a command is killed
+    #[arg(long, global = true, default_value_t = 30.0)]
+    timeout: f64,
+    /// How the namespaces, veth pair, addresses, netem qdisc, offloads and
+    /// nftables rules are configured
+    #[arg(long, global = true, value_enum, default_value_t = Backend::Command)]
+    backend: Backend,
+    #[command(subcommand)]
+    command: Command,
+}
+
+/// Loss rate a run is plotted and modelled against
+fn loss_rate(grid: &Grid, r: f64, p: f64) -> Result<f64> {
+    Ok(match grid.loss_axis {
+        LossAxis::Nominal => p,
+        LossAxis::Measured => {
+            cache::manifest(&grid.out, r, p)
+                .and_then(|e| e.loss())
+                .with_context(|| format!("no measured loss rate for r = {r}, p = {p}"))?
+                .0
+        }
+    })
+}
+
+fn summarize(grid: &Grid) -> Result<Summary> {
+    let mut throughputs = Vec::new();
+    for r in &grid.rtt {
+        let mut points = Vec::new();
+        for (_, p) in grid.runs().filter(|(e, _)| e == r) {
+            let measurements = load(&grid.out, *r, p)?;
+            let throughput = throughput(&measurements)
+                .with_context(|| format!("r = {r}, p = {p} in {}", grid.out.display()))?;
+            points.push((loss_rate(grid, *r, p)?, throughput));
+        }
+        if !points.is_empty() {
+            throughputs.push((*r, points));
+        }
+    }
+    Ok(throughputs)
+}
+
+fn sweep(
+    methodology: &dyn Methodology,
+    topology: &Topology,
+    grid: &Grid,
+    sender: &Sender,
+    cubic: &Cubic,
+    jobs: &Jobs,
+) -> Result<()> {
+    if !runner::dry_run() {
+        create_dir_all(&grid.out)?;
+    }
+    // applied first, as the configurations the cache is looked up with record
+    // the tcp_cubic parameters in effect
+    let _restore = cubic.apply()?;
+    let mut missing = Vec::new();
+    for (r, p) in grid.points() {
+        let config = Config::new(methodology, topology, sender, r, p)?;
+        match lookup(&grid.out, &config)? {
+            None => missing.push((r, p)),
+            // charts and reports read the runs of the configuration swept
+            Some(_) if !runner::dry_run() => select(&grid.out, r, p, &config.key()?)?,
+            Some(_) => {}
+        }
+    }
+    jobs.run(methodology, topology, sender, &grid.out, missing)
+}
+
+/// Charts every run of a grid and their throughput summary into `charts`
+fn chart(grid: &Grid, charts: &Path, models: &Models, style: &Style) -> Result<()> {
+    for (r, p) in grid.runs() {
+        let events = probe::load(&grid.out, r, p)?;
+        let measurements = load(&grid.out, r, p)?;
+        let chart = run_chart(r, p, &measurements, events.as_deref(), models, style)?;
+        chart.write(charts, style)?;
+    }
+    let summary = summary_chart(&summarize(grid)?, grid.loss_axis, models, style)?;
+    summary.write(charts, style)
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    if cli.dry_run && cli.backend == Backend::Netlink {
+        bail!("dry runs print the commands of the command backend");
+    }
+    backend::configure(cli.backend);
+    let Some(timeout) = Duration::try_from_secs_f64(cli.timeout)
+        .ok()
+        .filter(|e| !e.is_zero())
+    else {
+        bail!(
+            "timeout {} must be a positive number of seconds",
+            cli.timeout
+        );
+    };
+    runner::configure(cli.dry_run, timeout);
+    if cli.rootless {
+        rootless::enter()?;
+    }
+    cleanup::on_signal()?;
+    match cli.command {
+        Command::Run {
+            rtt,
+            loss,
+            out,
+            simulation,
+            models,
+            style,
+        } => {
+            let methodology = simulation.methodology.methodology();
+            simulation.check(&[rtt], &[loss])?;
+            let variant = simulation.variant()?;
+            let sender = simulation.sender(&variant)?;
+            if let Some(congestion) = &variant.congestion {
+                congestion::ensure(congestion)?;
+            }
+            let _restore = variant.cubic.apply()?;
+            let topology = Topology::default();
+            let _topology = prepare(methodology, &topology)?;
+            if runner::dry_run() {
+                return plan(methodology, &topology, &sender, rtt, loss);
+            }
+            let setup = runner::take();
+            create_dir_all(&out)?;
+            let config = Config::new(methodology, &topology, &sender, rtt, loss)?;
+            let run = simulate(methodology, &topology, &sender, rtt, loss)?;
+            let (measurements, events) = (run.measurements.clone(), run.probe.clone());
+            store(&out, config, run, &setup)?;
+            let chart = run_chart(rtt, loss, &measurements, events.as_deref(), &models, &style)?;
+            chart.write(&out, &style)?;
+        }
+        Command::Sweep {
+            grid,
+            simulation,
+            models,
+            style,
+            jobs,
+        } => {
+            simulation.check(&grid.rtt, &grid.loss)?;
+            let variant = simulation.variant()?;
+            let sender = simulation.sender(&variant)?;
+            if let Some(congestion) = &variant.congestion {
+                congestion::ensure(congestion)?;
+            }
+            sweep(
+                simulation.methodology.methodology(),
+                &Topology::default(),
+                &grid,
+                &sender,
+                &variant.cubic,
+                &jobs,
+            )?;
+            if !runner::dry_run() {
+                chart(&grid, &grid.out, &models, &style)?;
+            }
+        }
+        Command::Experiment {
+            file,
+            check,
+            out,
+            models,
+            style,
+            jobs,
+        } => {
+            let experiment = Experiment::load(&file)?;
+            let variants = experiment.variants();
+            if !check {
+                for congestion in variants.iter().filter_map(|e| e.congestion.as_ref()) {
+                    congestion::ensure(congestion)?;
+                }
+            }
+            for variant in &variants {
+                let grid = Grid {
+                    rtt: experiment.sweep.rtt.clone(),
+                    loss: experiment.sweep.loss.clone(),
+                    out: match variants.len() {
+                        1 => out.clone(),
+                        _ => out.join(variant.label()),
+                    },
+                    loss_axis: LossAxis::Nominal,
+                };
+                if check {
+                    for (r, p) in grid.points() {
+                        println!("{}: r = {r}, p = {p}", grid.out.display());
+                    }
+                } else {
+                    let sender = experiment.sender(variant)?;
+                    let (topology, cubic) = (&experiment.topology, &variant.cubic);
+                    sweep(&experiment, topology, &grid, &sender, cubic, &jobs)?;
+                    if !runner::dry_run() {
+                        chart(&grid, &grid.out, &models, &style)?;
+                    }
+                }
+            }
+        }
+        Command::Plot {
+            results,
+            models,
+            style,
+        } => {
+            for grid in results.grids()? {
+                let charts = results.charts(&grid.out);
+                create_dir_all(&charts)?;
+                chart(&grid, &charts, &models, &style)?;
+            }
+        }
+        Command::Compare {
+            results,
+            labels,
+            models,
+            style,
+        } => {
+            let grids = results.grids()?;
+            let labels = match labels.len() {
+                0 => grids.iter().map(|e| name(&e.out)).collect(),
+                n if n == grids.len() => labels,
+                n => bail!("{n} labels for {} result directories", grids.len()),
+            };
+            let charts = results.charts.unwrap_or_else(|| "compare".into());
+            create_dir_all(&charts)?;
+            let mut datasets = Vec::new();
+            for (label, grid) in labels.iter().zip(&grids) {
+                datasets.push((label.clone(), summarize(grid)?));
+            }
+            compare_summary(&charts, &datasets, results.loss_axis, &models, &style)?;
+            let common = grids[0]
+                .runs()
+                .filter(|e| grids.iter().all(|grid| grid.runs().any(|run| run == *e)));
+            for (r, p) in common {
+                let mut traces = Vec::new();
+                for (label, grid) in labels.iter().zip(&grids) {
+                    traces.push((label.clone(), load(&grid.out, r, p)?));
+                }
+                compare_traces(&charts, r, p, &traces, &style)?;
+            }
+        }
+        Command::Report {
+            results,
+            models,
+            style,
+        } => {
+            for grid in results.grids()? {
+                let charts = results.charts(&grid.out);
+                create_dir_all(&charts)?;
+                report::report(&grid, &charts, &models, &style)?;
+            }
+        }
+        Command::Analyze { results, models } => {
+            let mut header = String::from("out,rtt,loss,measured_loss,diverges,simulated");
+            for model in &models.models {
+                header += &format!(",{}", model.name());
+            }
+            println!("{header}");
+            for grid in results.grids()? {
+                for (r, p) in grid.runs() {
+                    let throughput = throughput(&load(&grid.out, r, p)?)
+                        .with_context(|| format!("r = {r}, p = {p} in {}", grid.out.display()))?;
+                    let loss = cache::manifest(&grid.out, r, p).and_then(|e| e.loss());
+                    let (measured, diverges) = match loss {
+                        Some((rate, diverges)) => (format!("{rate:.7}"), diverges.to_string()),
+                        None => Default::default(),
+                    };
+                    let out = grid.out.display();
+                    let mut line = format!("{out},{r},{p},{measured},{diverges},{throughput:.3}");
+                    let x = loss_rate(&grid, r, p)?;
+                    for model in &models.models {
+                        let estimation = model.throughput(&models.parameters, r, x);
+                        line += &format!(",{estimation:.3}");
+                    }
+                    println!("{line}");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::Cli;
+
+    #[test]
+    fn cli() {
+        Cli::command().debug_assert();
+    }
 }
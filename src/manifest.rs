@@ -0,0 +1,261 @@
+use std::{
+    collections::BTreeMap,
+    fs::{read_dir, read_to_string},
+    net::TcpStream,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use nix::sys::socket::{getsockopt, sockopt::TcpCongestion};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend, cache::Config, methodology::Topology, probe::Event, runner::Invocation, Measurement,
+};
+
+/// Written next to the CSV of every run
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub key: String,
+    pub config: Config,
+    /// Missing from manifests written before the system was recorded
+    #[serde(default)]
+    pub system: Option<System>,
+    #[serde(default)]
+    pub timing: Option<Timing>,
+    /// Loss applied by nftables, for methodologies that drop periodically
+    #[serde(default)]
+    pub drops: Option<Drops>,
+    #[serde(default)]
+    pub netem: Option<Netem>,
+    /// Warnings about the conditions of the run that the rest of the manifest
+    /// does not record, such as busy CPUs
+    #[serde(default)]
+    pub warnings: Vec<String>,
+}
+
+impl Manifest {
+    /// Loss rate measured over the run, and whether it diverges from the
+    /// nominal one
+    pub fn loss(&self) -> Option<(f64, bool)> {
+        measured(self.drops.as_ref(), self.netem.as_ref())
+    }
+}
+
+/// A finished run, along with the state of the system it ran on
+pub struct Run {
+    pub measurements: Vec<Measurement>,
+    /// Events traced with tcp_probe, if the sender asked for them
+    pub probe: Option<Vec<Event>>,
+    pub system: System,
+    pub timing: Timing,
+    pub drops: Option<Drops>,
+    pub netem: Option<Netem>,
+    /// Commands run from the impairment of the link to its teardown
+    pub commands: Vec<Invocation>,
+    pub warnings: Vec<String>,
+}
+
+impl Run {
+    /// Loss rate measured over the run, and whether it diverges from the
+    /// nominal one
+    pub fn loss(&self) -> Option<(f64, bool)> {
+        measured(self.drops.as_ref(), self.netem.as_ref())
+    }
+}
+
+/// State of the system as applied while a run was in progress
+#[derive(Serialize, Deserialize)]
+pub struct System {
+    /// Kernel release
+    pub kernel: String,
+    /// Parameters of the tcp_cubic module
+    pub tcp_cubic: BTreeMap<String, String>,
+    /// Offload features under the names of ethtool -k, by interface
+    pub offloads: BTreeMap<String, BTreeMap<String, String>>,
+    /// Qdiscs of the client namespace, as shown by tc or summarized from
+    /// netlink
+    pub qdisc: String,
+    /// Ruleset of the server namespace, as listed by nft or summarized from
+    /// nfnetlink
+    pub ruleset: String,
+    /// Congestion control of the sending socket
+    pub congestion: String,
+}
+
+impl System {
+    pub fn capture(topology: &Topology, stream: &TcpStream) -> Result<Self> {
+        let mut offloads = BTreeMap::new();
+        for (end, netns) in topology.ends() {
+            offloads.insert(end.into(), backend::features(&netns, end)?);
+        }
+        Ok(Self {
+            kernel: read_to_string("/proc/sys/kernel/osrelease")?.trim().into(),
+            tcp_cubic: tcp_cubic()?,
+            offloads,
+            qdisc: backend::qdiscs(&topology.client_netns())?,
+            ruleset: backend::ruleset(&topology.server_netns())?,
+            congestion: getsockopt(stream, TcpCongestion)?
+                .to_string_lossy()
+                .trim_end_matches('\0')
+                .into(),
+        })
+    }
+}
+
+/// Parameters of the tcp_cubic module, empty if it is not loaded
+pub fn tcp_cubic() -> Result<BTreeMap<String, String>> {
+    let mut parameters = BTreeMap::new();
+    if let Ok(entries) = read_dir("/sys/module/tcp_cubic/parameters") {
+        for entry in entries {
+            let entry = entry?;
+            let value = read_to_string(entry.path())?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            parameters.insert(name, value.trim().into());
+        }
+    }
+    Ok(parameters)
+}
+
+/// Standard deviations of the dropped packets past which a run is flagged
+const TOLERANCE: f64 = 3.0;
+
+/// Whether `dropped` out of `trials` packets is further from what a loss
+/// rate of `p` drops on average than counting statistics explain, which a
+/// fixed relative tolerance would not allow for on runs expected to drop a
+/// handful of packets or fewer
+fn diverges(dropped: u64, trials: u64, p: f64) -> bool {
+    let expected = trials as f64 * p;
+    (dropped as f64 - expected).abs() > TOLERANCE * (expected * (1.0 - p)).sqrt()
+}
+
+/// Prefers the counters of nftables, which only methodologies that drop
+/// periodically install, over the drops of netem
+fn measured(drops: Option<&Drops>, netem: Option<&Netem>) -> Option<(f64, bool)> {
+    match (drops, netem) {
+        (Some(drops), _) => Some((drops.rate, drops.diverges)),
+        (None, Some(netem)) => Some((netem.rate, netem.diverges)),
+        (None, None) => None,
+    }
+}
+
+/// Packets counted by the rules of `Topology::add_periodic_loss` over a run
+#[derive(Serialize, Deserialize)]
+pub struct Drops {
+    /// Packets that reached the server, dropped or not
+    pub seen: u64,
+    /// Packets dropped to apply the loss rate
+    pub periodic: u64,
+    /// Packets dropped for exceeding the MTU, such as GSO aggregates
+    pub oversized: u64,
+    /// Fraction of the packets seen that were dropped
+    pub rate: f64,
+    /// Whether the drops are off the nominal rate by more than `TOLERANCE`
+    /// standard deviations
+    pub diverges: bool,
+}
+
+impl Drops {
+    pub fn new(seen: u64, periodic: u64, oversized: u64, p: f64) -> Self {
+        let rate = match seen {
+            0 => 0.0,
+            _ => (periodic + oversized) as f64 / seen as f64,
+        };
+        Self {
+            seen,
+            periodic,
+            oversized,
+            rate,
+            diverges: diverges(periodic + oversized, seen, p),
+        }
+    }
+}
+
+/// Statistics of a qdisc, as reported by `tc -s -j`
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub struct QdiscStats {
+    /// Packets sent
+    pub packets: u64,
+    /// Bytes sent
+    pub bytes: u64,
+    /// Packets dropped, including those netem drops to apply loss
+    pub drops: u64,
+    pub overlimits: u64,
+    /// Bytes queued
+    pub backlog: u64,
+    /// Packets queued
+    pub qlen: u64,
+}
+
+/// Statistics of the root qdisc of the client at the start and end of a run
+#[derive(Serialize, Deserialize)]
+pub struct Netem {
+    pub start: QdiscStats,
+    pub end: QdiscStats,
+    /// Fraction of the packets enqueued in between that were dropped
+    pub rate: f64,
+    /// Loss rate netem was set to drop at, as a fraction, if it dropped at
+    /// all. The initial and intermediary2 methodologies give netem the
+    /// nominal rate as a percentage, as out.old and out.im2 were produced, so
+    /// that it drops a hundredth of it.
+    #[serde(default)]
+    pub nominal: Option<f64>,
+    /// Whether the drops are off the rate netem was set to by more than
+    /// `TOLERANCE` standard deviations
+    pub diverges: bool,
+}
+
+impl Netem {
+    pub fn new(start: QdiscStats, end: QdiscStats, nominal: Option<f64>) -> Self {
+        let sent = end.packets.saturating_sub(start.packets);
+        let dropped = end.drops.saturating_sub(start.drops);
+        let rate = match sent + dropped {
+            0 => 0.0,
+            enqueued => dropped as f64 / enqueued as f64,
+        };
+        Self {
+            start,
+            end,
+            rate,
+            nominal,
+            diverges: nominal.is_some_and(|e| diverges(dropped, sent + dropped, e)),
+        }
+    }
+}
+
+/// Wall clock bounds of a run, in seconds since the Unix epoch
+#[derive(Serialize, Deserialize)]
+pub struct Timing {
+    pub start: f64,
+    pub end: f64,
+    /// Time spent sending, in seconds
+    pub elapsed: f64,
+}
+
+impl Timing {
+    pub fn new(start: SystemTime, elapsed: Duration) -> Result<Self> {
+        let start = start.duration_since(UNIX_EPOCH)?;
+        Ok(Self {
+            start: start.as_secs_f64(),
+            end: (start + elapsed).as_secs_f64(),
+            elapsed: elapsed.as_secs_f64(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divergence() {
+        // netem set to 10^-7, as old gives it 10^-5, over a run of 60 s
+        assert!(!diverges(0, 50_000, 1e-7));
+        assert!(diverges(5, 50_000, 1e-7));
+        // periodic loss at 10^-3, with or without a few oversized drops
+        assert!(!diverges(100, 100_000, 1e-3));
+        assert!(!diverges(110, 100_000, 1e-3));
+        assert!(diverges(200, 100_000, 1e-3));
+        assert!(diverges(0, 100_000, 1e-3));
+    }
+}
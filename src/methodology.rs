@@ -0,0 +1,549 @@
+use std::{
+    fs::File,
+    io::{ErrorKind, Read, Write},
+    net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream},
+    os::fd::AsRawFd,
+    process,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread::{scope, sleep, spawn},
+    time::{Duration, Instant, SystemTime},
+};
+
+use anyhow::{bail, Context, Result};
+use nix::{
+    sched::{setns, CloneFlags},
+    sys::socket::{shutdown, Shutdown},
+    time::{clock_gettime, ClockId},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    backend,
+    cleanup::Guard,
+    congestion,
+    manifest::{Drops, Netem, QdiscStats, Run, System, Timing},
+    probe::Tracer,
+    runner::{self, dry_run, note},
+    tcp_info::tcp_info,
+    Measurement,
+};
+
+/// Describes how a methodology prepares the link and injects delay and loss
+pub trait Methodology: Sync {
+    /// Identifies the methodology in cache keys and manifests
+    fn name(&self) -> &str;
+
+    /// Settings that, along with the name, fully describe the methodology
+    fn settings(&self) -> Value {
+        Value::Null
+    }
+
+    /// Offloads the methodology turns on or off, which are checked once it is
+    /// set up
+    fn offloads(&self) -> Offloads {
+        Offloads::default()
+    }
+
+    /// Builds the topology, leaving the current thread in the client namespace,
+    /// and returns a guard that tears it down
+    fn setup(&self, topology: &Topology) -> Result<Guard> {
+        topology.create()
+    }
+
+    /// Installs the delay and loss of a single run
+    fn impair(&self, topology: &Topology, r: f64, p: f64) -> Result<()>;
+
+    /// Loss rate netem drops at for a nominal loss rate `p`, as a fraction, if
+    /// the methodology drops at random
+    fn random_loss(&self, _p: f64) -> Option<f64> {
+        None
+    }
+
+    /// Loss actually applied since `impair`, if the methodology counts it
+    fn drops(&self, _topology: &Topology, _p: f64) -> Result<Option<Drops>> {
+        Ok(None)
+    }
+
+    /// Removes whatever `impair` installed
+    fn teardown(&self, topology: &Topology, r: f64, p: f64) -> Result<()>;
+}
+
+/// Offloads to turn on or off on both ends of the veth pair, left untouched
+/// if unset
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Offloads {
+    pub tso: Option<bool>,
+    pub gso: Option<bool>,
+    pub gro: Option<bool>,
+    pub lro: Option<bool>,
+}
+
+impl Offloads {
+    /// Short and ethtool -k names of every offload, along with its state
+    pub fn features(&self) -> [(&'static str, &'static str, Option<bool>); 4] {
+        [
+            ("tso", "tcp-segmentation-offload", self.tso),
+            ("gso", "generic-segmentation-offload", self.gso),
+            ("gro", "generic-receive-offload", self.gro),
+            ("lro", "large-receive-offload", self.lro),
+        ]
+    }
+}
+
+/// Sets the methodology up and makes sure the offloads it asks for are in
+/// effect, which ethtool -K does not guarantee
+pub fn prepare(methodology: &dyn Methodology, topology: &Topology) -> Result<Guard> {
+    let guard = methodology.setup(topology)?;
+    if !dry_run() {
+        topology.check_offloads(&methodology.offloads())?;
+    }
+    Ok(guard)
+}
+
+/// Fails unless every RTT and loss rate of a grid can be applied, rates
+/// dropped periodically needing a period of two packets at least
+pub fn check_grid(rtt: &[f64], loss: &[f64], periodic: bool) -> Result<()> {
+    if rtt.is_empty() || loss.is_empty() {
+        bail!("sweep must have at least one rtt and one loss rate");
+    }
+    for r in rtt {
+        if !(*r > 0.0 && r.is_finite()) {
+            bail!("rtt {r} must be positive");
+        }
+    }
+    for p in loss {
+        if !(*p > 0.0 && *p < 1.0) {
+            bail!("loss rate {p} must be between 0 and 1");
+        }
+        if periodic && p.recip().round() < 2.0 {
+            bail!("loss rate {p} is too high to be applied periodically");
+        }
+    }
+    Ok(())
+}
+
+/// A server and a client namespace joined by a veth pair. The namespaces are
+/// unique to every topology, while the ends of the pair are named `server`
+/// and `client` after the side they live in.
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Topology {
+    pub server: String,
+    pub client: String,
+    pub mtu: u32,
+    /// Distinguishes the namespaces of this topology from those of any other
+    #[serde(skip)]
+    instance: usize,
+}
+
+static INSTANCES: AtomicUsize = AtomicUsize::new(0);
+
+impl Default for Topology {
+    fn default() -> Self {
+        Self {
+            server: "server".into(),
+            client: "client".into(),
+            mtu: 1500,
+            instance: INSTANCES.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+impl Topology {
+    /// Another topology like this one, with namespaces and addresses of its
+    /// own
+    pub fn sibling(&self) -> Self {
+        Self {
+            instance: INSTANCES.fetch_add(1, Ordering::Relaxed),
+            ..self.clone()
+        }
+    }
+
+    /// Address of a host in the /24 subnet of this topology, 10.1.1.0/24 for
+    /// the first one
+    fn address(&self, host: u8) -> Ipv4Addr {
+        let n = self.instance;
+        Ipv4Addr::new(10, (1 + n / 254) as u8, (1 + n % 254) as u8, host)
+    }
+
+    /// Address the server listens on, with a port of its own so that tracing
+    /// tells the connections of concurrent topologies apart
+    pub fn server_address(&self) -> SocketAddrV4 {
+        SocketAddrV4::new(self.address(1), 1234 + (self.instance % 1024) as u16)
+    }
+
+    fn netns(&self, end: &str) -> String {
+        format!("csee-{}-{}-{end}", process::id(), self.instance)
+    }
+
+    /// Namespace of the server end
+    pub fn server_netns(&self) -> String {
+        self.netns(&self.server)
+    }
+
+    /// Namespace of the client end
+    pub fn client_netns(&self) -> String {
+        self.netns(&self.client)
+    }
+
+    /// Both ends of the veth pair, along with the namespace each lives in
+    pub fn ends(&self) -> [(&str, String); 2] {
+        [
+            (&self.server, self.server_netns()),
+            (&self.client, self.client_netns()),
+        ]
+    }
+
+    /// Creates the namespaces and starts a server that discards everything it
+    /// receives, returning a guard that deletes them, along with everything
+    /// in them, when dropped
+    pub fn create(&self) -> Result<Guard> {
+        let Self {
+            server,
+            client,
+            mtu,
+            ..
+        } = self;
+        let (server_netns, client_netns) = (self.server_netns(), self.client_netns());
+        backend::add_netns(&server_netns)?;
+        let namespaces = Guard::new({
+            let namespaces = [server_netns.clone(), client_netns.clone()];
+            move || {
+                for netns in namespaces {
+                    let _ = backend::del_netns(&netns);
+                }
+            }
+        });
+        backend::add_netns(&client_netns)?;
+        backend::add_veth(server, &server_netns, client, &client_netns)?;
+        let (server_address, client_address) = (self.server_address(), self.address(2));
+        backend::add_address(
+            &server_netns,
+            server,
+            &format!("{}/24", server_address.ip()),
+        )?;
+        backend::add_address(&client_netns, client, &format!("{client_address}/24"))?;
+        backend::set_up(&server_netns, server, *mtu)?;
+        backend::set_up(&client_netns, client, *mtu)?;
+        if dry_run() {
+            note(format!(
+                "a server discarding everything listens on {server_address} in {server_netns}"
+            ));
+            return Ok(namespaces);
+        }
+        setns(
+            File::open(format!("/var/run/netns/{server_netns}"))?,
+            CloneFlags::empty(),
+        )?;
+        let listener = TcpListener::bind(server_address)?;
+        // the listener keeps the server namespace alive until it is shut down
+        let handle = listener.try_clone()?;
+        spawn(move || {
+            while let Ok((mut stream, _)) = listener.accept() {
+                while stream.read_exact(&mut [0; 1460]).is_ok() {}
+            }
+        });
+        let guard = Guard::new(move || {
+            let _ = shutdown(handle.as_raw_fd(), Shutdown::Both);
+            drop(namespaces);
+        });
+        setns(
+            File::open(format!("/var/run/netns/{client_netns}"))?,
+            CloneFlags::empty(),
+        )?;
+        Ok(guard)
+    }
+
+    /// Turns the offloads that are set on or off on both ends of the veth pair
+    pub fn set_offloads(&self, offloads: &Offloads) -> Result<()> {
+        for (end, netns) in self.ends() {
+            backend::set_offloads(&netns, end, offloads)?;
+        }
+        Ok(())
+    }
+
+    /// Disables TCP segmentation offload on both ends of the veth pair
+    pub fn disable_tso(&self) -> Result<()> {
+        self.set_offloads(&Offloads {
+            tso: Some(false),
+            ..Default::default()
+        })
+    }
+
+    /// Fails unless both ends of the veth pair report the offloads as set
+    pub fn check_offloads(&self, offloads: &Offloads) -> Result<()> {
+        for (end, netns) in self.ends() {
+            let reported = backend::features(&netns, end)?;
+            for (_, name, state) in offloads.features() {
+                let Some(state) = state else {
+                    continue;
+                };
+                let expected = if state { "on" } else { "off" };
+                let actual = reported
+                    .get(name)
+                    .and_then(|e| e.split_whitespace().next())
+                    .with_context(|| format!("ethtool does not report {name} of {end}"))?;
+                if actual != expected {
+                    bail!("{name} of {end} is {actual} rather than {expected}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Delays, and possibly drops, every packet leaving the client
+    pub fn add_netem(&self, impairment: &Impairment) -> Result<()> {
+        backend::add_netem(&self.client_netns(), &self.client, impairment)
+    }
+
+    /// Statistics of the root qdisc of the client
+    pub fn qdisc_stats(&self) -> Result<QdiscStats> {
+        backend::qdisc_stats(&self.client_netns(), &self.client)
+    }
+
+    pub fn del_netem(&self) -> Result<()> {
+        backend::del_qdisc(&self.client_netns(), &self.client)
+    }
+
+    /// Adds an empty ip filter table to the client
+    pub fn add_client_table(&self) -> Result<()> {
+        backend::add_table(&self.client_netns())
+    }
+
+    /// Drops every `1 / p`th packet arriving at the server, along with any
+    /// packet larger than the MTU if `oversized`
+    pub fn add_periodic_loss(&self, p: f64, oversized: bool) -> Result<()> {
+        let modulus = p.recip().round() as u32;
+        let mtu = oversized.then_some(self.mtu);
+        backend::add_periodic_loss(&self.server_netns(), mtu, modulus)
+    }
+
+    /// Reads back the counters of the rules installed by `add_periodic_loss`
+    pub fn read_periodic_loss(&self, p: f64, oversized: bool) -> Result<Drops> {
+        let mut counters = backend::periodic_loss_counters(&self.server_netns())?;
+        if !oversized {
+            counters.oversized = Some(0);
+        }
+        Ok(Drops::new(
+            counters
+                .seen
+                .context("no catch-all counter in the ruleset of the server")?,
+            counters
+                .periodic
+                .context("no periodic drop counter in the ruleset of the server")?,
+            counters
+                .oversized
+                .context("no oversized drop counter in the ruleset of the server")?,
+            p,
+        ))
+    }
+
+    /// Counts the packets larger than the MTU arriving at the server, before
+    /// any rule of `add_periodic_loss` drops them
+    pub fn add_oversized_counter(&self) -> Result<()> {
+        backend::add_oversized_counter(&self.server_netns(), self.mtu)
+    }
+
+    pub fn read_oversized_counter(&self) -> Result<u64> {
+        backend::oversized_counter(&self.server_netns())?
+            .context("no oversized packet counter in the ruleset of the server")
+    }
+
+    pub fn del_oversized_counter(&self) -> Result<()> {
+        backend::del_oversized_counter(&self.server_netns())
+    }
+
+    pub fn del_periodic_loss(&self) -> Result<()> {
+        backend::flush_ruleset(&self.server_netns())
+    }
+}
+
+/// Delay and random loss applied by netem
+#[derive(Debug, Default)]
+pub struct Impairment {
+    /// Delay, in seconds
+    pub delay: f64,
+    /// Jitter of the delay, in seconds
+    pub jitter: f64,
+    /// Random loss, in percent as netem takes it
+    pub loss: f64,
+    /// Correlation of the loss with the previous loss event, in percent
+    pub correlation: f64,
+}
+
+/// Packets counted by the rules of `Topology::add_periodic_loss`, if found
+#[derive(Default)]
+pub struct Counters {
+    pub seen: Option<u64>,
+    pub periodic: Option<u64>,
+    pub oversized: Option<u64>,
+}
+
+/// How the client sends during every run
+pub struct Sender {
+    /// Congestion control of the sending socket, the system default if unset
+    pub congestion: Option<String>,
+    pub duration: Duration,
+    /// Interval between two measurements
+    pub interval: Duration,
+    /// tcp_info fields to record alongside the congestion window
+    pub fields: Vec<&'static str>,
+    /// Trace the congestion window on every ACK with tcp_probe
+    pub probe: bool,
+}
+
+/// Runs a single experiment, sending as fast as possible for the duration of
+/// the sender
+pub fn simulate(
+    methodology: &dyn Methodology,
+    topology: &Topology,
+    sender: &Sender,
+    r: f64,
+    p: f64,
+) -> Result<Run> {
+    // checks that TSO being off keeps GSO packets off the link, whichever way
+    // the methodology drops
+    let check = methodology.offloads().tso == Some(false);
+    methodology.impair(topology, r, p)?;
+    if check {
+        topology.add_oversized_counter()?;
+    }
+    let run = topology.qdisc_stats().and_then(|start| {
+        let mut run = send(topology, sender)?;
+        run.drops = methodology.drops(topology, p)?;
+        if check {
+            let oversized = topology.read_oversized_counter()?;
+            if oversized > 0 {
+                bail!("{oversized} packets larger than the MTU crossed the link despite TSO being off");
+            }
+        }
+        let end = topology.qdisc_stats()?;
+        run.netem = Some(Netem::new(start, end, methodology.random_loss(p)));
+        Ok(run)
+    });
+    // removed first, as the teardown of periodic loss flushes the ruleset
+    if check {
+        topology.del_oversized_counter()?;
+    }
+    methodology.teardown(topology, r, p)?;
+    let mut run = run?;
+    run.commands = runner::take();
+    if let Some((rate, true)) = run.loss() {
+        eprintln!(
+            "warning: r = {r}, p = {p}: measured loss rate {rate:.7} diverges from the nominal one"
+        );
+    }
+    Ok(run)
+}
+
+/// Prints the commands a run would go through in a dry run, without sending
+/// anything
+pub fn plan(
+    methodology: &dyn Methodology,
+    topology: &Topology,
+    sender: &Sender,
+    r: f64,
+    p: f64,
+) -> Result<()> {
+    note(format!("r = {r}, p = {p}"));
+    let check = methodology.offloads().tso == Some(false);
+    methodology.impair(topology, r, p)?;
+    if check {
+        topology.add_oversized_counter()?;
+    }
+    note("the client sends to the server from its namespace meanwhile");
+    println!("sleep {}", sender.duration.as_secs_f64());
+    if check {
+        topology.del_oversized_counter()?;
+    }
+    methodology.teardown(topology, r, p)
+}
+
+fn send(topology: &Topology, sender: &Sender) -> Result<Run> {
+    let stream = TcpStream::connect(topology.server_address())?;
+    if let Some(congestion) = &sender.congestion {
+        congestion::set(&stream, congestion)?;
+    }
+    // lets the writer notice the end of the run even while the buffer is full
+    stream.set_write_timeout(Some(sender.interval))?;
+    let system = System::capture(topology, &stream)?;
+    let tracer = match sender.probe {
+        true => Some(Tracer::start(&stream)?),
+        false => None,
+    };
+    let start = SystemTime::now();
+    let written = AtomicUsize::new(0);
+    let done = AtomicBool::new(false);
+    let origin = clock_gettime(ClockId::CLOCK_MONOTONIC)?.into();
+    let now = Instant::now();
+    let (measurements, probe) = scope(|scope| {
+        let sampler = scope.spawn(|| sample(&stream, &written, &done, now, sender));
+        let prober = tracer
+            .as_ref()
+            .map(|tracer| scope.spawn(|| tracer.collect(origin, &done)));
+        let mut result = Ok(());
+        while now.elapsed() < sender.duration {
+            match (&stream).write(&[0; 1460]) {
+                Ok(n) => {
+                    written.fetch_add(n, Ordering::Relaxed);
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => {
+                    // stops the sampler and the tracer early, which are
+                    // joined before the error is returned
+                    done.store(true, Ordering::Relaxed);
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        let measurements = sampler.join().unwrap();
+        done.store(true, Ordering::Relaxed);
+        let probe = prober.map(|e| e.join().unwrap()).transpose();
+        result?;
+        anyhow::Ok((measurements?, probe?))
+    })?;
+    Ok(Run {
+        measurements,
+        probe,
+        system,
+        timing: Timing::new(start, now.elapsed())?,
+        drops: None,
+        netem: None,
+        commands: Vec::new(),
+        warnings: Vec::new(),
+    })
+}
+
+/// Samples the socket every interval until the end of the run, or until
+/// `done` is set, independently of how often the writer is blocked
+fn sample(
+    stream: &TcpStream,
+    written: &AtomicUsize,
+    done: &AtomicBool,
+    start: Instant,
+    sender: &Sender,
+) -> Result<Vec<Measurement>> {
+    let mut measurements = Vec::new();
+    let mut next = start + sender.interval;
+    while next <= start + sender.duration && !done.load(Ordering::Relaxed) {
+        sleep(next.saturating_duration_since(Instant::now()));
+        let time = start.elapsed().as_secs_f64();
+        let bytes_transferred = written.load(Ordering::Relaxed);
+        let snapshot = tcp_info(stream)?;
+        measurements.push(Measurement {
+            time,
+            bytes_transferred,
+            congestion_window: snapshot.info.snd_cwnd as usize,
+            fields: sender
+                .fields
+                .iter()
+                .map(|name| Ok((*name, snapshot.get(name)?)))
+                .collect::<Result<_>>()?,
+        });
+        next += sender.interval;
+    }
+    Ok(measurements)
+}
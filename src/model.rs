@@ -0,0 +1,186 @@
+use clap::{Args, ValueEnum};
+
+/// Steady state models of the average congestion window under periodic loss
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Model {
+    /// Reno, after Mathis et al.
+    Mathis,
+    /// Reno with retransmission timeouts, after Padhye et al.
+    Pftk,
+    /// CUBIC with the Reno window as a floor, per RFC 8312
+    #[value(name = "cubic-rfc8312")]
+    Cubic8312,
+    /// CUBIC with the Reno-friendly AIMD window as a floor, per RFC 9438
+    #[value(name = "cubic-rfc9438")]
+    Cubic9438,
+    /// HighSpeed TCP, per RFC 3649
+    Highspeed,
+    /// Scalable TCP, after Kelly
+    Scalable,
+}
+
+/// Parameters of the models, each falling back to the value the model was
+/// published with if unset
+#[derive(Args, Clone, Copy)]
+pub struct Parameters {
+    /// Scaling constant of the cubic function
+    #[arg(id = "model_c", long = "model-c", default_value_t = 0.4)]
+    pub c: f64,
+    /// Multiplicative decrease factor
+    #[arg(id = "model_beta", long = "model-beta")]
+    pub beta: Option<f64>,
+    /// Additive increase of the Reno-friendly window of RFC 9438, in packets
+    /// per RTT
+    #[arg(id = "model_alpha", long = "model-alpha")]
+    pub alpha: Option<f64>,
+    /// Retransmission timeout of the PFTK model, in seconds, RTT + 200 ms if
+    /// unset
+    #[arg(id = "model_rto", long = "model-rto")]
+    pub rto: Option<f64>,
+    /// Bytes per packet, which throughputs are computed from
+    #[arg(id = "model_mss", long = "model-mss", default_value_t = 1560.0)]
+    pub mss: f64,
+}
+
+/// Average window of an AIMD flow adding `alpha` per RTT and multiplying by
+/// `beta` on loss
+fn aimd(alpha: f64, beta: f64, p: f64) -> f64 {
+    (alpha * (1.0 + beta) / (2.0 * (1.0 - beta) * p)).sqrt()
+}
+
+fn cubic(c: f64, beta: f64, r: f64, p: f64) -> f64 {
+    (c * (3.0 + beta) / (4.0 * (1.0 - beta))).powf(0.25) * (r / p).powf(0.75)
+}
+
+impl Model {
+    pub fn name(self) -> &'static str {
+        match self {
+            Model::Mathis => "Mathis",
+            Model::Pftk => "PFTK",
+            Model::Cubic8312 => "CUBIC (RFC 8312)",
+            Model::Cubic9438 => "CUBIC (RFC 9438)",
+            Model::Highspeed => "HighSpeed",
+            Model::Scalable => "Scalable",
+        }
+    }
+
+    /// Average congestion window, in packets
+    pub fn window(self, parameters: &Parameters, r: f64, p: f64) -> f64 {
+        let Parameters { c, beta, alpha, .. } = *parameters;
+        match self {
+            Model::Mathis => aimd(1.0, beta.unwrap_or(0.5), p),
+            Model::Pftk => {
+                let t0 = parameters.rto.unwrap_or(r + 0.2);
+                let b = 1.0;
+                let delay = r * (2.0 * b * p / 3.0).sqrt()
+                    + t0 * f64::min(1.0, 3.0 * (3.0 * b * p / 8.0).sqrt())
+                        * p
+                        * (1.0 + 32.0 * p * p);
+                r / delay
+            }
+            Model::Cubic8312 => {
+                let beta = beta.unwrap_or(0.7);
+                f64::max(cubic(c, beta, r, p), (3.0 / 2.0 / p).powf(0.5))
+            }
+            Model::Cubic9438 => {
+                let beta = beta.unwrap_or(0.7);
+                let alpha = alpha.unwrap_or(3.0 * (1.0 - beta) / (1.0 + beta));
+                f64::max(cubic(c, beta, r, p), aimd(alpha, beta, p))
+            }
+            Model::Highspeed => 0.12 / p.powf(0.835),
+            Model::Scalable => {
+                let beta = beta.unwrap_or(0.875);
+                let a: f64 = 0.01;
+                a.ln_1p() / (p * -beta.ln())
+            }
+        }
+    }
+
+    /// Average throughput, in MB/s
+    pub fn throughput(self, parameters: &Parameters, r: f64, p: f64) -> f64 {
+        self.window(parameters, r, p) / r * parameters.mss / 1024.0 / 1024.0
+    }
+}
+
+/// Models to compare the simulation against
+#[derive(Args, Clone)]
+pub struct Models {
+    /// Models to overlay on the charts, as a comma separated list
+    #[arg(
+        long = "model",
+        value_enum,
+        value_delimiter = ',',
+        default_value = "cubic-rfc8312"
+    )]
+    pub models: Vec<Model>,
+    #[command(flatten)]
+    pub parameters: Parameters,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUBLISHED: Parameters = Parameters {
+        c: 0.4,
+        beta: None,
+        alpha: None,
+        rto: None,
+        mss: 1560.0,
+    };
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() <= expected * 1e-3,
+            "{actual} is not {expected}"
+        );
+    }
+
+    #[test]
+    fn mathis() {
+        // sqrt(3 / 2p)
+        assert_close(Model::Mathis.window(&PUBLISHED, 0.1, 0.01), 12.247);
+    }
+
+    #[test]
+    fn pftk() {
+        // with a timeout of RTT + 200 ms
+        assert_close(Model::Pftk.window(&PUBLISHED, 0.1, 0.01), 11.471);
+        // a longer timeout costs more of the window
+        let parameters = Parameters {
+            rto: Some(1.0),
+            ..PUBLISHED
+        };
+        assert_close(Model::Pftk.window(&parameters, 0.1, 0.01), 9.9920);
+    }
+
+    #[test]
+    fn cubic() {
+        // 1.054 (RTT / p)^0.75, as RFC 8312 gives it for C = 0.4 and
+        // beta = 0.7
+        for model in [Model::Cubic8312, Model::Cubic9438] {
+            assert_close(model.window(&PUBLISHED, 1.0, 1e-4), 1053.8);
+            // the Reno-friendly window takes over on short RTTs, and is
+            // the same in both RFCs with the default alpha
+            assert_close(model.window(&PUBLISHED, 0.1, 0.01), 12.247);
+        }
+    }
+
+    #[test]
+    fn highspeed() {
+        // 38 packets at p = 10^-3, per the table of RFC 3649
+        assert_close(Model::Highspeed.window(&PUBLISHED, 0.1, 1e-3), 38.387);
+    }
+
+    #[test]
+    fn scalable() {
+        // log(1 + a) / (-log(1 - b) p) with a = 0.01 and b = 0.125
+        assert_close(Model::Scalable.window(&PUBLISHED, 0.1, 0.01), 7.4517);
+    }
+
+    #[test]
+    fn throughput() {
+        // a window of sqrt(150) packets of 1560 bytes every 100 ms
+        assert_close(Model::Mathis.throughput(&PUBLISHED, 0.1, 0.01), 0.18221);
+    }
+}
@@ -0,0 +1,1026 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error,
+    ffi::CString,
+    fmt,
+    fs::{create_dir_all, remove_file, File, OpenOptions},
+    net::Ipv4Addr,
+    os::fd::{AsRawFd, OwnedFd},
+    panic::resume_unwind,
+    thread::scope,
+};
+
+use anyhow::{Context, Result};
+use nix::{
+    errno::Errno,
+    libc,
+    mount::{mount, umount2, MntFlags, MsFlags},
+    net::if_::if_nametoindex,
+    sched::{setns, unshare, CloneFlags},
+    sys::socket::{
+        bind, recv, send, socket, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol,
+        SockType,
+    },
+};
+
+use crate::{
+    manifest::QdiscStats,
+    methodology::{Counters, Impairment, Offloads},
+};
+
+const NETNS: &str = "/var/run/netns";
+
+/// A netlink request the kernel refused
+#[derive(Debug)]
+pub struct Error {
+    pub request: &'static str,
+    pub errno: Errno,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} failed: {}", self.request, self.errno)
+    }
+}
+
+impl error::Error for Error {}
+
+const REQUEST: u16 = libc::NLM_F_REQUEST as u16;
+const ACK: u16 = libc::NLM_F_ACK as u16;
+const DUMP: u16 = libc::NLM_F_DUMP as u16;
+const CREATE: u16 = libc::NLM_F_CREATE as u16;
+const EXCL: u16 = libc::NLM_F_EXCL as u16;
+const APPEND: u16 = libc::NLM_F_APPEND as u16;
+
+/// A netlink message under construction
+struct Message {
+    buffer: Vec<u8>,
+    /// Offsets of the nests that are still open
+    nests: Vec<usize>,
+}
+
+impl Message {
+    /// Starts a message with its family specific header
+    fn new(kind: u16, flags: u16, header: &[u8]) -> Self {
+        let mut message = Self {
+            buffer: Vec::new(),
+            nests: Vec::new(),
+        };
+        message.buffer.extend_from_slice(&0u32.to_ne_bytes());
+        message.buffer.extend_from_slice(&kind.to_ne_bytes());
+        message.buffer.extend_from_slice(&flags.to_ne_bytes());
+        // sequence number and port, filled in by `Socket::request`
+        message.buffer.extend_from_slice(&[0; 8]);
+        message.buffer.extend_from_slice(header);
+        message.align();
+        message
+    }
+
+    fn align(&mut self) {
+        self.buffer.resize(self.buffer.len().next_multiple_of(4), 0);
+    }
+
+    fn flags(&self) -> u16 {
+        u16::from_ne_bytes([self.buffer[6], self.buffer[7]])
+    }
+
+    fn attr(&mut self, kind: u16, data: &[u8]) -> &mut Self {
+        let len = 4 + data.len() as u16;
+        self.buffer.extend_from_slice(&len.to_ne_bytes());
+        self.buffer.extend_from_slice(&kind.to_ne_bytes());
+        self.buffer.extend_from_slice(data);
+        self.align();
+        self
+    }
+
+    fn u32(&mut self, kind: u16, value: u32) -> &mut Self {
+        self.attr(kind, &value.to_ne_bytes())
+    }
+
+    /// Big endian, as nftables expects
+    fn be32(&mut self, kind: u16, value: u32) -> &mut Self {
+        self.attr(kind, &value.to_be_bytes())
+    }
+
+    fn str(&mut self, kind: u16, value: &str) -> &mut Self {
+        let value = CString::new(value).expect("no NUL in attribute");
+        self.attr(kind, value.as_bytes_with_nul())
+    }
+
+    /// Opens a nest, which lasts until the matching `end`
+    fn nest(&mut self, kind: u16) -> &mut Self {
+        self.nests.push(self.buffer.len());
+        self.attr(kind | libc::NLA_F_NESTED as u16, &[])
+    }
+
+    /// Opens an attribute holding a struct followed by attributes, as netem
+    /// options do, which lasts until the matching `end`
+    fn nest_with(&mut self, kind: u16, header: &[u8]) -> &mut Self {
+        self.nests.push(self.buffer.len());
+        self.attr(kind, header)
+    }
+
+    fn end(&mut self) -> &mut Self {
+        let start = self.nests.pop().expect("unbalanced nest");
+        let len = (self.buffer.len() - start) as u16;
+        self.buffer[start..start + 2].copy_from_slice(&len.to_ne_bytes());
+        self
+    }
+
+    fn finish(mut self, seq: u32) -> Vec<u8> {
+        assert!(self.nests.is_empty(), "unbalanced nest");
+        let len = self.buffer.len() as u32;
+        self.buffer[..4].copy_from_slice(&len.to_ne_bytes());
+        self.buffer[8..12].copy_from_slice(&seq.to_ne_bytes());
+        self.buffer
+    }
+}
+
+/// Attributes of a message or nest, by type
+fn attributes(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if data.len() < 4 {
+            return None;
+        }
+        let len = u16::from_ne_bytes([data[0], data[1]]) as usize;
+        let kind = u16::from_ne_bytes([data[2], data[3]]) & libc::NLA_TYPE_MASK as u16;
+        if len < 4 || len > data.len() {
+            return None;
+        }
+        let value = &data[4..len];
+        data = &data[len.next_multiple_of(4).min(data.len())..];
+        Some((kind, value))
+    })
+}
+
+fn attribute(data: &[u8], kind: u16) -> Option<&[u8]> {
+    attributes(data).find(|(e, _)| *e == kind).map(|(_, e)| e)
+}
+
+struct Socket {
+    fd: OwnedFd,
+}
+
+impl Socket {
+    fn open(protocol: SockProtocol, request: &'static str) -> Result<Self, Error> {
+        let error = |errno| Error { request, errno };
+        let fd = socket(
+            AddressFamily::Netlink,
+            SockType::Raw,
+            SockFlag::SOCK_CLOEXEC,
+            protocol,
+        )
+        .map_err(error)?;
+        bind(fd.as_raw_fd(), &NetlinkAddr::new(0, 0)).map_err(error)?;
+        Ok(Self { fd })
+    }
+
+    /// Sends the messages at once and waits for every acknowledgement and
+    /// dump they asked for, returning the payloads of the replies
+    fn request(
+        &self,
+        request: &'static str,
+        messages: Vec<Message>,
+    ) -> Result<Vec<(u16, Vec<u8>)>, Error> {
+        let error = |errno| Error { request, errno };
+        let mut buffer = Vec::new();
+        let mut pending = 0;
+        for (seq, message) in messages.into_iter().enumerate() {
+            if message.flags() & (ACK | DUMP) != 0 {
+                pending += 1;
+            }
+            buffer.extend(message.finish(seq as u32 + 1));
+        }
+        send(self.fd.as_raw_fd(), &buffer, MsgFlags::empty()).map_err(error)?;
+        let mut replies = Vec::new();
+        let mut buffer = vec![0; 1 << 16];
+        while pending > 0 {
+            let n = recv(self.fd.as_raw_fd(), &mut buffer, MsgFlags::empty()).map_err(error)?;
+            let mut data = &buffer[..n];
+            while data.len() >= 16 {
+                let len = u32::from_ne_bytes(data[..4].try_into().unwrap()) as usize;
+                let kind = u16::from_ne_bytes([data[4], data[5]]);
+                if len < 16 || len > data.len() {
+                    break;
+                }
+                let payload = &data[16..len];
+                match kind as i32 {
+                    libc::NLMSG_ERROR => {
+                        let code = i32::from_ne_bytes(payload[..4].try_into().unwrap());
+                        if code != 0 {
+                            return Err(error(Errno::from_i32(-code)));
+                        }
+                        pending -= 1;
+                    }
+                    libc::NLMSG_DONE => pending -= 1,
+                    _ => replies.push((kind, payload.to_vec())),
+                }
+                data = &data[len.next_multiple_of(4).min(data.len())..];
+            }
+        }
+        Ok(replies)
+    }
+}
+
+/// Runs `f` on a thread that has entered the network namespace
+fn within<T: Send>(netns: &str, f: impl FnOnce() -> Result<T> + Send) -> Result<T> {
+    let file = File::open(format!("{NETNS}/{netns}"))
+        .with_context(|| format!("no network namespace named {netns}"))?;
+    scope(|s| {
+        s.spawn(move || {
+            setns(file, CloneFlags::CLONE_NEWNET)?;
+            f()
+        })
+        .join()
+        .unwrap_or_else(|e| resume_unwind(e))
+    })
+}
+
+fn index(end: &str) -> Result<u32> {
+    if_nametoindex(end).with_context(|| format!("no interface named {end}"))
+}
+
+/// Creates a named network namespace the way `ip netns add` does, by
+/// bind mounting it under /var/run/netns
+pub fn add_netns(netns: &str) -> Result<()> {
+    create_dir_all(NETNS)?;
+    // mounts under the directory must propagate to every mount namespace,
+    // so it has to be a shared mount point of its own
+    let shared = || {
+        mount(
+            None::<&str>,
+            NETNS,
+            None::<&str>,
+            MsFlags::MS_SHARED | MsFlags::MS_REC,
+            None::<&str>,
+        )
+    };
+    if shared() == Err(Errno::EINVAL) {
+        mount(
+            Some(NETNS),
+            NETNS,
+            None::<&str>,
+            MsFlags::MS_BIND | MsFlags::MS_REC,
+            None::<&str>,
+        )?;
+        shared()?;
+    }
+    let path = format!("{NETNS}/{netns}");
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .with_context(|| format!("failed to create {path}"))?;
+    let bound = scope(|s| {
+        s.spawn(|| {
+            unshare(CloneFlags::CLONE_NEWNET)?;
+            mount(
+                Some("/proc/thread-self/ns/net"),
+                path.as_str(),
+                None::<&str>,
+                MsFlags::MS_BIND,
+                None::<&str>,
+            )
+        })
+        .join()
+        .unwrap_or_else(|e| resume_unwind(e))
+    });
+    if let Err(e) = bound {
+        let _ = remove_file(&path);
+        Err(e).context("failed to create a network namespace")?;
+    }
+    Ok(())
+}
+
+pub fn del_netns(netns: &str) -> Result<()> {
+    let path = format!("{NETNS}/{netns}");
+    umount2(path.as_str(), MntFlags::MNT_DETACH)?;
+    remove_file(path)?;
+    Ok(())
+}
+
+fn ifinfomsg(index: u32, flags: u32, change: u32) -> Vec<u8> {
+    let mut header = vec![libc::AF_UNSPEC as u8, 0, 0, 0];
+    header.extend_from_slice(&index.to_ne_bytes());
+    header.extend_from_slice(&flags.to_ne_bytes());
+    header.extend_from_slice(&change.to_ne_bytes());
+    header
+}
+
+const VETH_INFO_PEER: u16 = 1;
+
+/// Creates a veth pair with each end in its own namespace
+pub fn add_veth(end: &str, netns: &str, peer: &str, peer_netns: &str) -> Result<()> {
+    let netns = File::open(format!("{NETNS}/{netns}"))?;
+    let peer_netns = File::open(format!("{NETNS}/{peer_netns}"))?;
+    let mut message = Message::new(
+        libc::RTM_NEWLINK,
+        REQUEST | ACK | CREATE | EXCL,
+        &ifinfomsg(0, 0, 0),
+    );
+    message
+        .str(libc::IFLA_IFNAME, end)
+        .u32(libc::IFLA_NET_NS_FD, netns.as_raw_fd() as u32)
+        .nest(libc::IFLA_LINKINFO)
+        .str(libc::IFLA_INFO_KIND, "veth")
+        .nest(libc::IFLA_INFO_DATA)
+        .nest_with(VETH_INFO_PEER, &ifinfomsg(0, 0, 0))
+        .str(libc::IFLA_IFNAME, peer)
+        .u32(libc::IFLA_NET_NS_FD, peer_netns.as_raw_fd() as u32)
+        .end()
+        .end()
+        .end();
+    Socket::open(SockProtocol::NetlinkRoute, "creating the veth pair")?
+        .request("creating the veth pair", vec![message])?;
+    Ok(())
+}
+
+/// Adds an IPv4 address, such as `10.1.1.1/24`
+pub fn add_address(netns: &str, end: &str, address: &str) -> Result<()> {
+    let (local, prefix) = address
+        .split_once('/')
+        .with_context(|| format!("{address} has no prefix length"))?;
+    let local: Ipv4Addr = local.parse()?;
+    let prefix: u8 = prefix.parse()?;
+    within(netns, || {
+        let mut header = vec![libc::AF_INET as u8, prefix, 0, libc::RT_SCOPE_UNIVERSE];
+        header.extend_from_slice(&index(end)?.to_ne_bytes());
+        let mut message = Message::new(libc::RTM_NEWADDR, REQUEST | ACK | CREATE | EXCL, &header);
+        message
+            .attr(libc::IFA_LOCAL, &local.octets())
+            .attr(libc::IFA_ADDRESS, &local.octets());
+        Socket::open(SockProtocol::NetlinkRoute, "adding an address")?
+            .request("adding an address", vec![message])?;
+        Ok(())
+    })
+}
+
+pub fn set_up(netns: &str, end: &str, mtu: u32) -> Result<()> {
+    within(netns, || {
+        let up = libc::IFF_UP as u32;
+        let mut message = Message::new(
+            libc::RTM_NEWLINK,
+            REQUEST | ACK,
+            &ifinfomsg(index(end)?, up, up),
+        );
+        message.u32(libc::IFLA_MTU, mtu);
+        Socket::open(SockProtocol::NetlinkRoute, "setting the link up")?
+            .request("setting the link up", vec![message])?;
+        Ok(())
+    })
+}
+
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+const ETHTOOL_MSG_FEATURES_GET: u8 = 11;
+const ETHTOOL_MSG_FEATURES_SET: u8 = 12;
+const ETHTOOL_A_FEATURES_HEADER: u16 = 1;
+const ETHTOOL_A_FEATURES_HW: u16 = 2;
+const ETHTOOL_A_FEATURES_WANTED: u16 = 3;
+const ETHTOOL_A_FEATURES_ACTIVE: u16 = 4;
+const ETHTOOL_A_FEATURES_NOCHANGE: u16 = 5;
+const ETHTOOL_A_HEADER_DEV_NAME: u16 = 2;
+const ETHTOOL_A_BITSET_BITS: u16 = 3;
+const ETHTOOL_A_BITSET_BITS_BIT: u16 = 1;
+const ETHTOOL_A_BITSET_BIT_NAME: u16 = 2;
+const ETHTOOL_A_BITSET_BIT_VALUE: u16 = 3;
+
+/// Features of the kernel that each offload of ethtool stands for
+fn kernel_features(offload: &str) -> &'static [&'static str] {
+    match offload {
+        "tso" => &[
+            "tx-tcp-segmentation",
+            "tx-tcp-ecn-segmentation",
+            "tx-tcp-mangleid-segmentation",
+            "tx-tcp6-segmentation",
+        ],
+        "gso" => &["tx-generic-segmentation"],
+        "gro" => &["rx-gro"],
+        "lro" => &["rx-lro"],
+        _ => unreachable!("unknown offload {offload}"),
+    }
+}
+
+/// Resolves the generic netlink family of ethtool
+fn ethtool(socket: &Socket) -> Result<u16> {
+    let mut message = Message::new(GENL_ID_CTRL, REQUEST | ACK, &[CTRL_CMD_GETFAMILY, 1, 0, 0]);
+    message.str(CTRL_ATTR_FAMILY_NAME, "ethtool");
+    let replies = socket.request("resolving ethtool netlink", vec![message])?;
+    let family = replies
+        .iter()
+        .find_map(|(_, e)| attribute(&e[4..], CTRL_ATTR_FAMILY_ID))
+        .context("the kernel does not support ethtool netlink")?;
+    Ok(u16::from_ne_bytes(family[..2].try_into()?))
+}
+
+/// Sets the offloads over ethtool netlink
+pub fn set_offloads(netns: &str, end: &str, offloads: &Offloads) -> Result<()> {
+    let features = offloads
+        .features()
+        .into_iter()
+        .filter_map(|(name, _, state)| Some((kernel_features(name), state?)))
+        .collect::<Vec<_>>();
+    if features.is_empty() {
+        return Ok(());
+    }
+    within(netns, || {
+        let socket = Socket::open(SockProtocol::NetlinkGeneric, "setting offloads")?;
+        let mut message = Message::new(
+            ethtool(&socket)?,
+            REQUEST | ACK,
+            &[ETHTOOL_MSG_FEATURES_SET, 1, 0, 0],
+        );
+        message
+            .nest(ETHTOOL_A_FEATURES_HEADER)
+            .str(ETHTOOL_A_HEADER_DEV_NAME, end)
+            .end()
+            .nest(ETHTOOL_A_FEATURES_WANTED)
+            .nest(ETHTOOL_A_BITSET_BITS);
+        for (names, state) in features {
+            for name in names {
+                message
+                    .nest(ETHTOOL_A_BITSET_BITS_BIT)
+                    .str(ETHTOOL_A_BITSET_BIT_NAME, name);
+                if state {
+                    message.attr(ETHTOOL_A_BITSET_BIT_VALUE, &[]);
+                }
+                message.end();
+            }
+        }
+        message.end().end();
+        socket.request("setting offloads", vec![message])?;
+        Ok(())
+    })
+}
+
+/// Names of the features set in a bitset, which the kernel lists without a
+/// mask
+fn names(bitset: Option<&[u8]>) -> BTreeSet<String> {
+    let bits = bitset.and_then(|e| attribute(e, ETHTOOL_A_BITSET_BITS));
+    attributes(bits.unwrap_or_default())
+        .filter(|(kind, _)| *kind == ETHTOOL_A_BITSET_BITS_BIT)
+        .filter_map(|(_, e)| attribute(e, ETHTOOL_A_BITSET_BIT_NAME))
+        .map(text)
+        .filter(|e| !e.is_empty())
+        .collect()
+}
+
+/// Features of an end of the veth pair over ethtool netlink, under the names
+/// ethtool -k gives them: each offload is on if any of its kernel features
+/// is, and fixed if all of them are
+pub fn features(netns: &str, end: &str) -> Result<BTreeMap<String, String>> {
+    let replies = within(netns, || {
+        let socket = Socket::open(SockProtocol::NetlinkGeneric, "listing features")?;
+        // the acknowledgement follows the reply, telling that it is in
+        let mut message = Message::new(
+            ethtool(&socket)?,
+            REQUEST | ACK,
+            &[ETHTOOL_MSG_FEATURES_GET, 1, 0, 0],
+        );
+        message
+            .nest(ETHTOOL_A_FEATURES_HEADER)
+            .str(ETHTOOL_A_HEADER_DEV_NAME, end)
+            .end();
+        Ok(socket.request("listing features", vec![message])?)
+    })?;
+    let (_, reply) = replies
+        .first()
+        .with_context(|| format!("no features reported for {end}"))?;
+    let attributes = &reply[4..];
+    let hw = names(attribute(attributes, ETHTOOL_A_FEATURES_HW));
+    let active = names(attribute(attributes, ETHTOOL_A_FEATURES_ACTIVE));
+    let nochange = names(attribute(attributes, ETHTOOL_A_FEATURES_NOCHANGE));
+    let state = |on: bool, fixed: bool| {
+        format!(
+            "{}{}",
+            if on { "on" } else { "off" },
+            if fixed { " [fixed]" } else { "" }
+        )
+    };
+    let fixed = |name: &str| !hw.contains(name) || nochange.contains(name);
+    let mut features = BTreeMap::new();
+    for name in hw.union(&active) {
+        features.insert(name.clone(), state(active.contains(name), fixed(name)));
+    }
+    for (short, name, _) in Offloads::default().features() {
+        let kernel = kernel_features(short);
+        let on = kernel.iter().any(|e| active.contains(*e));
+        features.insert(name.into(), state(on, kernel.iter().all(|e| fixed(e))));
+    }
+    Ok(features)
+}
+
+const TC_H_ROOT: u32 = 0xffff_ffff;
+const TCA_NETEM_CORR: u16 = 1;
+const TCA_NETEM_LATENCY64: u16 = 10;
+const TCA_NETEM_JITTER64: u16 = 11;
+const TCA_STATS_BASIC: u16 = 1;
+const TCA_STATS_QUEUE: u16 = 3;
+const TCA_STATS_PKT64: u16 = 8;
+
+fn tcmsg(index: u32, parent: u32) -> Vec<u8> {
+    let mut header = vec![libc::AF_UNSPEC as u8, 0, 0, 0];
+    header.extend_from_slice(&index.to_ne_bytes());
+    // handle, left for the kernel to allocate
+    header.extend_from_slice(&0u32.to_ne_bytes());
+    header.extend_from_slice(&parent.to_ne_bytes());
+    header.extend_from_slice(&0u32.to_ne_bytes());
+    header
+}
+
+/// Probability in percent scaled to a u32, as netem takes it
+fn probability(percent: f64) -> u32 {
+    (percent / 100.0 * u32::MAX as f64).round() as u32
+}
+
+pub fn add_netem(netns: &str, end: &str, impairment: &Impairment) -> Result<()> {
+    let Impairment {
+        delay,
+        jitter,
+        loss,
+        correlation,
+    } = *impairment;
+    // latency, limit, loss, gap, duplicate and jitter, with the delays given
+    // in nanoseconds as attributes instead
+    let mut options = Vec::new();
+    for e in [0, 1000, probability(loss), 0, 0, 0] {
+        options.extend_from_slice(&e.to_ne_bytes());
+    }
+    let mut correlations = Vec::new();
+    for e in [0, probability(correlation), 0] {
+        correlations.extend_from_slice(&e.to_ne_bytes());
+    }
+    within(netns, || {
+        let mut message = Message::new(
+            libc::RTM_NEWQDISC,
+            REQUEST | ACK | CREATE | EXCL,
+            &tcmsg(index(end)?, TC_H_ROOT),
+        );
+        message
+            .str(libc::TCA_KIND, "netem")
+            .nest_with(libc::TCA_OPTIONS, &options)
+            .attr(TCA_NETEM_CORR, &correlations)
+            .attr(
+                TCA_NETEM_LATENCY64,
+                &((delay * 1e9).round() as i64).to_ne_bytes(),
+            )
+            .attr(
+                TCA_NETEM_JITTER64,
+                &((jitter * 1e9).round() as i64).to_ne_bytes(),
+            )
+            .end();
+        Socket::open(SockProtocol::NetlinkRoute, "adding netem")?
+            .request("adding netem", vec![message])?;
+        Ok(())
+    })
+}
+
+pub fn qdisc_stats(netns: &str, end: &str) -> Result<QdiscStats> {
+    within(netns, || {
+        let index = index(end)?;
+        let message = Message::new(libc::RTM_GETQDISC, REQUEST | DUMP, &tcmsg(0, 0));
+        let replies = Socket::open(SockProtocol::NetlinkRoute, "listing qdiscs")?
+            .request("listing qdiscs", vec![message])?;
+        let stats = replies
+            .iter()
+            .filter(|(kind, e)| *kind == libc::RTM_NEWQDISC && e.len() >= 20)
+            .find(|(_, e)| {
+                u32::from_ne_bytes(e[4..8].try_into().unwrap()) == index
+                    && u32::from_ne_bytes(e[12..16].try_into().unwrap()) == TC_H_ROOT
+            })
+            .and_then(|(_, e)| attribute(&e[20..], libc::TCA_STATS2))
+            .with_context(|| format!("no root qdisc on {end}"))?;
+        let u32_at = |data: &[u8], i: usize| u32::from_ne_bytes(data[i..i + 4].try_into().unwrap());
+        let basic = attribute(stats, TCA_STATS_BASIC).context("no basic qdisc statistics")?;
+        let queue = attribute(stats, TCA_STATS_QUEUE).context("no qdisc queue statistics")?;
+        let packets = match attribute(stats, TCA_STATS_PKT64) {
+            Some(e) => u64::from_ne_bytes(e[..8].try_into()?),
+            None => u32_at(basic, 8).into(),
+        };
+        Ok(QdiscStats {
+            packets,
+            bytes: u64::from_ne_bytes(basic[..8].try_into()?),
+            drops: u32_at(queue, 8).into(),
+            overlimits: u32_at(queue, 16).into(),
+            backlog: u32_at(queue, 4).into(),
+            qlen: u32_at(queue, 0).into(),
+        })
+    })
+}
+
+/// Names of the interfaces of the current namespace, by index
+fn links() -> Result<BTreeMap<u32, String>> {
+    let message = Message::new(libc::RTM_GETLINK, REQUEST | DUMP, &ifinfomsg(0, 0, 0));
+    let replies = Socket::open(SockProtocol::NetlinkRoute, "listing links")?
+        .request("listing links", vec![message])?;
+    let mut links = BTreeMap::new();
+    for (_, reply) in replies.iter().filter(|(_, e)| e.len() >= 16) {
+        if let Some(name) = attribute(&reply[16..], libc::IFLA_IFNAME) {
+            let index = u32::from_ne_bytes(reply[4..8].try_into()?);
+            links.insert(index, text(name));
+        }
+    }
+    Ok(links)
+}
+
+/// Qdiscs of the namespace, one line each with the kind, handle, interface
+/// and parent that tc qdisc show starts its lines with
+pub fn qdiscs(netns: &str) -> Result<String> {
+    within(netns, || {
+        let links = links()?;
+        let message = Message::new(libc::RTM_GETQDISC, REQUEST | DUMP, &tcmsg(0, 0));
+        let replies = Socket::open(SockProtocol::NetlinkRoute, "listing qdiscs")?
+            .request("listing qdiscs", vec![message])?;
+        let mut qdiscs = String::new();
+        for (_, reply) in replies.iter().filter(|(_, e)| e.len() >= 20) {
+            let u32_at = |i: usize| u32::from_ne_bytes(reply[i..i + 4].try_into().unwrap());
+            let kind = attribute(&reply[20..], libc::TCA_KIND).map_or_else(String::new, text);
+            let dev = links.get(&u32_at(4)).map_or("?", String::as_str);
+            let parent = match u32_at(12) {
+                TC_H_ROOT => "root".into(),
+                e => format!("parent {:x}:{:x}", e >> 16, e & 0xffff),
+            };
+            qdiscs += &format!("qdisc {kind} {:x}: dev {dev} {parent}\n", u32_at(8) >> 16);
+        }
+        Ok(qdiscs)
+    })
+}
+
+pub fn del_qdisc(netns: &str, end: &str) -> Result<()> {
+    within(netns, || {
+        let message = Message::new(
+            libc::RTM_DELQDISC,
+            REQUEST | ACK,
+            &tcmsg(index(end)?, TC_H_ROOT),
+        );
+        Socket::open(SockProtocol::NetlinkRoute, "deleting the qdisc")?
+            .request("deleting the qdisc", vec![message])?;
+        Ok(())
+    })
+}
+
+const NFTA_TABLE_NAME: u16 = 1;
+const NFTA_CHAIN_TABLE: u16 = 1;
+const NFTA_CHAIN_NAME: u16 = 3;
+const NFTA_CHAIN_HOOK: u16 = 4;
+const NFTA_CHAIN_TYPE: u16 = 7;
+const NFTA_HOOK_HOOKNUM: u16 = 1;
+const NFTA_HOOK_PRIORITY: u16 = 2;
+const NFTA_RULE_TABLE: u16 = 1;
+const NFTA_RULE_CHAIN: u16 = 2;
+const NFTA_RULE_EXPRESSIONS: u16 = 4;
+const NFTA_LIST_ELEM: u16 = 1;
+const NFTA_EXPR_NAME: u16 = 1;
+const NFTA_EXPR_DATA: u16 = 2;
+const NFTA_META_DREG: u16 = 1;
+const NFTA_META_KEY: u16 = 2;
+const NFTA_CMP_SREG: u16 = 1;
+const NFTA_CMP_OP: u16 = 2;
+const NFTA_CMP_DATA: u16 = 3;
+const NFTA_DATA_VALUE: u16 = 1;
+const NFTA_DATA_VERDICT: u16 = 2;
+const NFTA_VERDICT_CODE: u16 = 1;
+const NFTA_IMMEDIATE_DREG: u16 = 1;
+const NFTA_IMMEDIATE_DATA: u16 = 2;
+const NFTA_COUNTER_PACKETS: u16 = 2;
+const NFTA_BYTEORDER_SREG: u16 = 1;
+const NFTA_BYTEORDER_DREG: u16 = 2;
+const NFTA_BYTEORDER_OP: u16 = 3;
+const NFTA_BYTEORDER_LEN: u16 = 4;
+const NFTA_BYTEORDER_SIZE: u16 = 5;
+const NFT_BYTEORDER_HTON: u32 = 1;
+const NFTA_NG_DREG: u16 = 1;
+const NFTA_NG_MODULUS: u16 = 2;
+const NFTA_NG_TYPE: u16 = 3;
+
+fn nft(kind: u16, flags: u16, family: i32) -> Message {
+    let kind = (libc::NFNL_SUBSYS_NFTABLES as u16) << 8 | kind;
+    Message::new(kind, flags, &[family as u8, libc::NFNETLINK_V0 as u8, 0, 0])
+}
+
+/// Applies the messages as a single transaction
+fn batch(request: &'static str, messages: Vec<Message>) -> Result<(), Error> {
+    let subsystem = (libc::NFNL_SUBSYS_NFTABLES as u16).to_be_bytes();
+    let header = [libc::AF_UNSPEC as u8, libc::NFNETLINK_V0 as u8];
+    let header = [header, subsystem].concat();
+    let mut batch = vec![Message::new(
+        libc::NFNL_MSG_BATCH_BEGIN as u16,
+        REQUEST,
+        &header,
+    )];
+    batch.extend(messages);
+    batch.push(Message::new(
+        libc::NFNL_MSG_BATCH_END as u16,
+        REQUEST,
+        &header,
+    ));
+    Socket::open(SockProtocol::NetlinkNetFilter, request)?.request(request, batch)?;
+    Ok(())
+}
+
+fn table(name: &str) -> Message {
+    let mut message = nft(
+        libc::NFT_MSG_NEWTABLE as u16,
+        REQUEST | ACK | CREATE,
+        libc::NFPROTO_IPV4,
+    );
+    message.str(NFTA_TABLE_NAME, name);
+    message
+}
+
+/// Adds the ip filter table
+pub fn add_table(netns: &str) -> Result<()> {
+    within(netns, || {
+        Ok(batch("adding a table", vec![table("filter")])?)
+    })
+}
+
+/// Adds the input chain of a table, hooked at `priority`
+fn chain(table: &str, priority: i32) -> Message {
+    let mut message = nft(
+        libc::NFT_MSG_NEWCHAIN as u16,
+        REQUEST | ACK | CREATE,
+        libc::NFPROTO_IPV4,
+    );
+    message
+        .str(NFTA_CHAIN_TABLE, table)
+        .str(NFTA_CHAIN_NAME, "input")
+        .nest(NFTA_CHAIN_HOOK)
+        .be32(NFTA_HOOK_HOOKNUM, libc::NF_INET_LOCAL_IN as u32)
+        .be32(NFTA_HOOK_PRIORITY, priority as u32)
+        .end()
+        .str(NFTA_CHAIN_TYPE, "filter");
+    message
+}
+
+/// Starts a rule of the input chain of a table, to be followed by its
+/// expressions
+fn rule(table: &str) -> Message {
+    let mut message = nft(
+        libc::NFT_MSG_NEWRULE as u16,
+        REQUEST | ACK | CREATE | APPEND,
+        libc::NFPROTO_IPV4,
+    );
+    message
+        .str(NFTA_RULE_TABLE, table)
+        .str(NFTA_RULE_CHAIN, "input")
+        .nest(NFTA_RULE_EXPRESSIONS);
+    message
+}
+
+fn expression(message: &mut Message, name: &str, data: impl FnOnce(&mut Message)) {
+    message.nest(NFTA_LIST_ELEM).str(NFTA_EXPR_NAME, name);
+    message.nest(NFTA_EXPR_DATA);
+    data(message);
+    message.end().end();
+}
+
+fn counter(message: &mut Message) {
+    expression(message, "counter", |_| {});
+}
+
+/// Compares register 1 against a value byte by byte, so that only equality
+/// holds for values in host byte order
+fn cmp(message: &mut Message, op: i32, value: [u8; 4]) {
+    expression(message, "cmp", |e| {
+        e.be32(NFTA_CMP_SREG, libc::NFT_REG_1 as u32)
+            .be32(NFTA_CMP_OP, op as u32)
+            .nest(NFTA_CMP_DATA)
+            .attr(NFTA_DATA_VALUE, &value)
+            .end();
+    });
+}
+
+/// Matches packets longer than `mtu`
+fn longer(message: &mut Message, mtu: u32) {
+    expression(message, "meta", |e| {
+        e.be32(NFTA_META_DREG, libc::NFT_REG_1 as u32)
+            .be32(NFTA_META_KEY, libc::NFT_META_LEN as u32);
+    });
+    expression(message, "byteorder", |e| {
+        e.be32(NFTA_BYTEORDER_SREG, libc::NFT_REG_1 as u32)
+            .be32(NFTA_BYTEORDER_DREG, libc::NFT_REG_1 as u32)
+            .be32(NFTA_BYTEORDER_OP, NFT_BYTEORDER_HTON)
+            .be32(NFTA_BYTEORDER_LEN, 4)
+            .be32(NFTA_BYTEORDER_SIZE, 4);
+    });
+    cmp(message, libc::NFT_CMP_GT, mtu.to_be_bytes());
+}
+
+fn drop_packet(message: &mut Message) {
+    expression(message, "immediate", |e| {
+        e.be32(NFTA_IMMEDIATE_DREG, libc::NFT_REG_VERDICT as u32)
+            .nest(NFTA_IMMEDIATE_DATA)
+            .nest(NFTA_DATA_VERDICT)
+            .be32(NFTA_VERDICT_CODE, libc::NF_DROP as u32)
+            .end()
+            .end();
+    });
+}
+
+/// Counts every packet arriving in the namespace, drops those larger than
+/// `mtu` if given and every `modulus`th of the others
+pub fn add_periodic_loss(netns: &str, mtu: Option<u32>, modulus: u32) -> Result<()> {
+    let mut seen = rule("filter");
+    counter(&mut seen);
+    seen.end();
+    let mut messages = vec![table("filter"), chain("filter", 0), seen];
+    if let Some(mtu) = mtu {
+        let mut oversized = rule("filter");
+        longer(&mut oversized, mtu);
+        counter(&mut oversized);
+        drop_packet(&mut oversized);
+        oversized.end();
+        messages.push(oversized);
+    }
+    let mut periodic = rule("filter");
+    expression(&mut periodic, "numgen", |e| {
+        e.be32(NFTA_NG_DREG, libc::NFT_REG_1 as u32)
+            .be32(NFTA_NG_MODULUS, modulus)
+            .be32(NFTA_NG_TYPE, libc::NFT_NG_INCREMENTAL as u32);
+    });
+    cmp(&mut periodic, libc::NFT_CMP_EQ, (modulus - 1).to_ne_bytes());
+    counter(&mut periodic);
+    drop_packet(&mut periodic);
+    periodic.end();
+    messages.push(periodic);
+    within(netns, || Ok(batch("adding periodic loss", messages)?))
+}
+
+/// Sends a dump request of nftables objects of every family
+fn dump(kind: i32, request: &'static str) -> Result<Vec<(u16, Vec<u8>)>> {
+    let message = nft(kind as u16, REQUEST | DUMP, libc::NFPROTO_UNSPEC);
+    Ok(Socket::open(SockProtocol::NetlinkNetFilter, request)?.request(request, vec![message])?)
+}
+
+/// A rule as listed over nfnetlink
+struct Rule {
+    family: u8,
+    table: String,
+    chain: String,
+    /// Names of its expressions, in order
+    names: Vec<String>,
+    /// Packets counted by its counter, if it has one
+    packets: Option<u64>,
+}
+
+/// Every rule of the current namespace
+fn rules() -> Result<Vec<Rule>> {
+    let mut rules = Vec::new();
+    for (_, reply) in dump(libc::NFT_MSG_GETRULE, "listing rules")? {
+        let attributes = &reply[4..];
+        let mut names = Vec::new();
+        let mut packets = None;
+        let expressions = attribute(attributes, NFTA_RULE_EXPRESSIONS).unwrap_or_default();
+        for (_, expression) in elements(expressions) {
+            let name = text(attribute(expression, NFTA_EXPR_NAME).unwrap_or_default());
+            if name == "counter" {
+                packets = attribute(expression, NFTA_EXPR_DATA)
+                    .and_then(|e| attribute(e, NFTA_COUNTER_PACKETS))
+                    .and_then(|e| Some(u64::from_be_bytes(e.try_into().ok()?)));
+            }
+            names.push(name);
+        }
+        rules.push(Rule {
+            family: reply[0],
+            table: text(attribute(attributes, NFTA_RULE_TABLE).unwrap_or_default()),
+            chain: text(attribute(attributes, NFTA_RULE_CHAIN).unwrap_or_default()),
+            names,
+            packets,
+        });
+    }
+    Ok(rules)
+}
+
+/// Rules of the input chain of an ip table
+fn counted(netns: &str, table: &str) -> Result<Vec<Rule>> {
+    let rules = within(netns, rules)?;
+    Ok(rules
+        .into_iter()
+        .filter(|e| e.family == libc::NFPROTO_IPV4 as u8 && e.table == table && e.chain == "input")
+        .collect())
+}
+
+pub fn periodic_loss_counters(netns: &str) -> Result<Counters> {
+    let mut counters = Counters::default();
+    for Rule { names, packets, .. } in counted(netns, "filter")? {
+        if names.len() == 1 {
+            counters.seen = packets;
+        } else if names.iter().any(|e| e == "numgen") {
+            counters.periodic = packets;
+        } else if names.iter().any(|e| e == "meta") {
+            counters.oversized = packets;
+        }
+    }
+    Ok(counters)
+}
+
+/// Counts the packets larger than `mtu` arriving in the namespace, in a table
+/// of its own hooked before the filter table so that none of its drops are
+/// missed
+pub fn add_oversized_counter(netns: &str, mtu: u32) -> Result<()> {
+    let mut oversized = rule("check");
+    longer(&mut oversized, mtu);
+    counter(&mut oversized);
+    oversized.end();
+    within(netns, || {
+        let messages = vec![table("check"), chain("check", -1), oversized];
+        Ok(batch("adding an oversized packet counter", messages)?)
+    })
+}
+
+pub fn oversized_counter(netns: &str) -> Result<Option<u64>> {
+    let rules = counted(netns, "check")?;
+    Ok(rules.into_iter().find_map(|e| e.packets))
+}
+
+pub fn del_oversized_counter(netns: &str) -> Result<()> {
+    within(netns, || {
+        let mut message = nft(
+            libc::NFT_MSG_DELTABLE as u16,
+            REQUEST | ACK,
+            libc::NFPROTO_IPV4,
+        );
+        message.str(NFTA_TABLE_NAME, "check");
+        Ok(batch(
+            "deleting the oversized packet counter",
+            vec![message],
+        )?)
+    })
+}
+
+/// Text of a string attribute, without its terminating null byte
+fn text(data: &[u8]) -> String {
+    String::from_utf8_lossy(data.strip_suffix(b"\0").unwrap_or(data)).into_owned()
+}
+
+/// Elements of a list, such as the expressions of a rule
+fn elements(data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    attributes(data).filter(|(kind, _)| *kind == NFTA_LIST_ELEM)
+}
+
+fn family(family: u8) -> &'static str {
+    match family as i32 {
+        libc::NFPROTO_INET => "inet",
+        libc::NFPROTO_IPV4 => "ip",
+        libc::NFPROTO_ARP => "arp",
+        libc::NFPROTO_NETDEV => "netdev",
+        libc::NFPROTO_BRIDGE => "bridge",
+        libc::NFPROTO_IPV6 => "ip6",
+        _ => "unknown",
+    }
+}
+
+/// Ruleset of the namespace in the layout of nft list ruleset, with every
+/// rule given by the names of its expressions and the packets it counted
+pub fn ruleset(netns: &str) -> Result<String> {
+    within(netns, || {
+        let tables = dump(libc::NFT_MSG_GETTABLE, "listing tables")?;
+        let chains = dump(libc::NFT_MSG_GETCHAIN, "listing chains")?;
+        let rules = rules()?;
+        let mut ruleset = String::new();
+        for (_, table) in &tables {
+            let name = text(attribute(&table[4..], NFTA_TABLE_NAME).unwrap_or_default());
+            ruleset += &format!("table {} {name} {{\n", family(table[0]));
+            for (_, chain) in &chains {
+                let attributes = &chain[4..];
+                let same = chain[0] == table[0];
+                let chain = text(attribute(attributes, NFTA_CHAIN_NAME).unwrap_or_default());
+                if !same
+                    || text(attribute(attributes, NFTA_CHAIN_TABLE).unwrap_or_default()) != name
+                {
+                    continue;
+                }
+                ruleset += &format!("\tchain {chain} {{\n");
+                for rule in &rules {
+                    if rule.family != table[0] || rule.table != name || rule.chain != chain {
+                        continue;
+                    }
+                    let mut line = rule.names.join(" ");
+                    if let Some(packets) = rule.packets {
+                        line = line.replace("counter", &format!("counter packets {packets}"));
+                    }
+                    ruleset += &format!("\t\t{line}\n");
+                }
+                ruleset += "\t}\n";
+            }
+            ruleset += "}\n";
+        }
+        Ok(ruleset)
+    })
+}
+
+pub fn flush_ruleset(netns: &str) -> Result<()> {
+    within(netns, || {
+        // a table deletion that names no table deletes them all
+        let message = nft(
+            libc::NFT_MSG_DELTABLE as u16,
+            REQUEST | ACK,
+            libc::NFPROTO_UNSPEC,
+        );
+        Ok(batch("flushing the ruleset", vec![message])?)
+    })
+}
@@ -0,0 +1,212 @@
+use std::{
+    fs::{create_dir, remove_dir, write, OpenOptions},
+    io::{ErrorKind, Read},
+    net::TcpStream,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+    thread::sleep,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use csv::{Reader, Writer};
+use nix::libc::O_NONBLOCK;
+use serde::{Deserialize, Serialize};
+
+use crate::{cleanup::Guard, runner::exec};
+
+const TRACEFS: &str = "/sys/kernel/tracing";
+
+/// State of the sending socket on the arrival of an ACK, as reported by the
+/// tcp:tcp_probe tracepoint
+#[derive(Clone, Deserialize, Serialize)]
+pub struct Event {
+    /// Time since the start of the run, in seconds
+    pub time: f64,
+    pub snd_cwnd: u32,
+    pub ssthresh: u32,
+    /// Smoothed RTT, in microseconds
+    pub srtt: u32,
+    pub snd_nxt: u32,
+}
+
+/// A tracefs instance recording the tcp_probe events of a single connection,
+/// removed when dropped
+pub struct Tracer {
+    instance: PathBuf,
+    /// Addresses of the connection as tcp_probe prints them
+    src: String,
+    dest: String,
+    _guard: Guard,
+}
+
+impl Tracer {
+    /// Starts tracing the connection of `stream`. The kernel filters events on
+    /// its ports, as ftrace cannot match the address arrays of tcp_probe, and
+    /// `collect` on its addresses, which tell apart the connections of
+    /// concurrent jobs that happen to share ports.
+    pub fn start(stream: &TcpStream) -> Result<Self> {
+        let tracefs = Path::new(TRACEFS);
+        if !tracefs.join("instances").exists() {
+            exec(format!("mount -t tracefs nodev {TRACEFS}"), None)
+                .context("tracefs is not available")?;
+        }
+        let (src, dest) = (stream.local_addr()?, stream.peer_addr()?);
+        let (sport, dport) = (src.port(), dest.port());
+        let instance = tracefs
+            .join("instances")
+            .join(format!("csee-{}-{sport}-{dport}", process::id()));
+        create_dir(&instance)
+            .with_context(|| format!("failed to create {}", instance.display()))?;
+        let tracer = Self {
+            _guard: Guard::new({
+                let instance = instance.clone();
+                move || {
+                    let _ = write(instance.join("events/tcp/tcp_probe/enable"), "0");
+                    let _ = remove_dir(&instance);
+                }
+            }),
+            instance,
+            src: src.to_string(),
+            dest: dest.to_string(),
+        };
+        let event = tracer.instance.join("events/tcp/tcp_probe");
+        // matches the clock of `Instant`, which the run is timed with
+        write(tracer.instance.join("trace_clock"), "mono")?;
+        write(
+            event.join("filter"),
+            format!("family == 2 && sport == {sport} && dport == {dport}"),
+        )?;
+        write(event.join("enable"), "1").context("tcp_probe is not available")?;
+        Ok(tracer)
+    }
+
+    /// Reads events until `done` is set and every pending event has been read,
+    /// timing them relative to `origin`, the monotonic time the run started
+    pub fn collect(&self, origin: Duration, done: &AtomicBool) -> Result<Vec<Event>> {
+        let mut pipe = OpenOptions::new()
+            .read(true)
+            .custom_flags(O_NONBLOCK)
+            .open(self.instance.join("trace_pipe"))?;
+        let mut events = Vec::new();
+        let mut pending = Vec::new();
+        let mut buffer = [0; 65536];
+        loop {
+            // checked before reading so that nothing written before `done`
+            // is set gets left behind
+            let finished = done.load(Ordering::Relaxed);
+            let n = match pipe.read(&mut buffer) {
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => 0,
+                Err(e) => Err(e)?,
+            };
+            if n == 0 {
+                if finished {
+                    break;
+                }
+                sleep(Duration::from_millis(10));
+                continue;
+            }
+            pending.extend_from_slice(&buffer[..n]);
+            let end = pending
+                .iter()
+                .rposition(|e| *e == b'\n')
+                .map_or(0, |i| i + 1);
+            for line in String::from_utf8_lossy(&pending[..end]).lines() {
+                events.extend(parse(line, &self.src, &self.dest, origin));
+            }
+            pending.drain(..end);
+        }
+        Ok(events)
+    }
+}
+
+/// Parses a line of trace_pipe such as
+/// `<idle>-0 [001] ..s2. 1234.567890: tcp_probe: family=AF_INET ...`, if it
+/// traces the connection from `src` to `dest`
+fn parse(line: &str, src: &str, dest: &str, origin: Duration) -> Option<Event> {
+    let (head, fields) = line.split_once(": tcp_probe: ")?;
+    let timestamp: f64 = head.split_whitespace().last()?.parse().ok()?;
+    let text = |name: &str| {
+        fields
+            .split_whitespace()
+            .find_map(|e| e.strip_prefix(name)?.strip_prefix('='))
+    };
+    if text("src")? != src || text("dest")? != dest {
+        return None;
+    }
+    let field = |name: &str| {
+        let value = text(name)?;
+        match value.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => value.parse().ok(),
+        }
+    };
+    Some(Event {
+        time: timestamp - origin.as_secs_f64(),
+        snd_cwnd: field("snd_cwnd")?,
+        ssthresh: field("ssthresh")?,
+        srtt: field("srtt")?,
+        snd_nxt: field("snd_nxt")?,
+    })
+}
+
+fn path(out: &Path, r: f64, p: f64) -> PathBuf {
+    out.join(format!("{r}_{:.5}.probe.csv", p))
+}
+
+pub fn save(out: &Path, r: f64, p: f64, events: &[Event]) -> Result<()> {
+    let mut writer = Writer::from_path(path(out, r, p))?;
+    for event in events {
+        writer.serialize(event)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Loads the events of a run, if it was traced
+pub fn load(out: &Path, r: f64, p: f64) -> Result<Option<Vec<Event>>> {
+    let path = path(out, r, p);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let events = Reader::from_path(path)?
+        .deserialize()
+        .collect::<Result<_, _>>()?;
+    Ok(Some(events))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINE: &str = "iperf-1234 [001] ..s2. 1234.500000: tcp_probe: family=AF_INET \
+        src=10.1.1.2:5201 dest=10.1.1.1:42424 mark=0x0 data_len=0 snd_nxt=0x1f4 \
+        snd_una=0x1f0 snd_cwnd=10 ssthresh=2147483647 snd_wnd=65536 srtt=100250 \
+        rcv_wnd=65536 sock_cookie=2 skbaddr=0000000000000000 skaddr=0000000000000000";
+
+    #[test]
+    fn fields() {
+        let origin = Duration::from_secs(1234);
+        let event = parse(LINE, "10.1.1.2:5201", "10.1.1.1:42424", origin).unwrap();
+        assert_eq!(event.time, 0.5);
+        assert_eq!(event.snd_cwnd, 10);
+        assert_eq!(event.ssthresh, 2147483647);
+        assert_eq!(event.srtt, 100250);
+        assert_eq!(event.snd_nxt, 500);
+    }
+
+    #[test]
+    fn other_connections() {
+        let origin = Duration::ZERO;
+        // same ports, other addresses
+        assert!(parse(LINE, "10.2.1.2:5201", "10.1.1.1:42424", origin).is_none());
+        assert!(parse(LINE, "10.1.1.2:5201", "10.2.1.1:42424", origin).is_none());
+        // reversed direction
+        assert!(parse(LINE, "10.1.1.1:42424", "10.1.1.2:5201", origin).is_none());
+        let other = LINE.replace("tcp_probe", "tcp_retransmit_skb");
+        assert!(parse(&other, "10.1.1.2:5201", "10.1.1.1:42424", origin).is_none());
+    }
+}
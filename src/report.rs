@@ -0,0 +1,377 @@
+use std::{collections::BTreeMap, fs::write, path::Path};
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde_json::Value;
+
+use crate::{
+    cache::{self, Config},
+    chart::{escape, run_chart, summary_chart, Chart, Format, Style},
+    load, loss_rate,
+    manifest::Manifest,
+    methodology::Offloads,
+    model::Models,
+    name, probe, summarize, throughput, Grid, Method,
+};
+
+/// A part of a report, which renders as Markdown or HTML
+enum Block {
+    Heading(usize, String),
+    Paragraph(String),
+    List(Vec<String>),
+    Table(Vec<String>, Vec<Vec<String>>),
+    Chart(Chart),
+    /// Preformatted text, collapsed under a summary
+    Details(String, String),
+}
+
+impl Block {
+    fn markdown(&self, image: Format) -> String {
+        match self {
+            Block::Heading(level, text) => format!("{} {text}\n", "#".repeat(*level)),
+            Block::Paragraph(text) => format!("{text}\n"),
+            Block::List(items) => items.iter().map(|e| format!("- {e}\n")).collect(),
+            Block::Table(header, rows) => {
+                let row = |cells: &[String]| {
+                    let cells = cells.iter().map(|e| e.replace('|', r"\|"));
+                    format!("| {} |\n", cells.collect::<Vec<_>>().join(" | "))
+                };
+                let mut table = row(header);
+                table += &row(&vec!["---".into(); header.len()]);
+                table.extend(rows.iter().map(|e| row(e)));
+                table
+            }
+            Block::Chart(chart) => {
+                format!("![{}]({}.{})\n", chart.title, chart.name, image.extension())
+            }
+            Block::Details(summary, text) => format!(
+                "<details>\n<summary>{}</summary>\n\n```json\n{text}\n```\n\n</details>\n",
+                escape(summary)
+            ),
+        }
+    }
+
+    fn html(&self, style: &Style) -> Result<String> {
+        Ok(match self {
+            Block::Heading(level, text) => format!("<h{level}>{}</h{level}>", escape(text)),
+            Block::Paragraph(text) => format!("<p>{}</p>", escape(text)),
+            Block::List(items) => {
+                let items = items.iter().map(|e| format!("<li>{}</li>", escape(e)));
+                format!("<ul>{}</ul>", items.collect::<String>())
+            }
+            Block::Table(header, rows) => {
+                let row = |cells: &[String], tag| {
+                    let cells = cells
+                        .iter()
+                        .map(|e| format!("<{tag}>{}</{tag}>", escape(e)));
+                    format!("<tr>{}</tr>", cells.collect::<String>())
+                };
+                let rows = rows.iter().map(|e| row(e, "td")).collect::<String>();
+                format!("<table>{}{rows}</table>", row(header, "th"))
+            }
+            Block::Chart(chart) => format!("<figure>{}</figure>", chart.svg(style)?),
+            Block::Details(summary, text) => format!(
+                "<details><summary>{}</summary><pre>{}</pre></details>",
+                escape(summary),
+                escape(text)
+            ),
+        })
+    }
+}
+
+/// Hard-coded methodology a run was configured with, if any
+fn method(config: &Config) -> Option<Method> {
+    Method::value_variants()
+        .iter()
+        .copied()
+        .find(|e| e.methodology().name() == config.methodology)
+}
+
+/// Offloads the methodology of a run turned on or off
+fn offloads(config: &Config) -> Offloads {
+    match method(config) {
+        Some(method) => method.methodology().offloads(),
+        None => config
+            .settings
+            .get("offloads")
+            .and_then(|e| serde_json::from_value(e.clone()).ok())
+            .unwrap_or_default(),
+    }
+}
+
+/// Flattens settings into `name = value` items, leaving unset ones out
+fn settings(prefix: &str, value: &Value, items: &mut Vec<String>) {
+    match value {
+        Value::Null => {}
+        Value::Object(fields) => {
+            for (name, value) in fields {
+                let name = match prefix {
+                    "" => name.clone(),
+                    _ => format!("{prefix}.{name}"),
+                };
+                settings(&name, value, items);
+            }
+        }
+        Value::String(value) => items.push(format!("{prefix} = {value}")),
+        value => items.push(format!("{prefix} = {value}")),
+    }
+}
+
+fn methodology(manifest: &Manifest) -> Vec<Block> {
+    let config = &manifest.config;
+    let description = method(config)
+        .and_then(|e| Some(e.to_possible_value()?.get_help()?.to_string()))
+        .unwrap_or_else(|| "described by an experiment file".into());
+    let mut blocks = vec![Block::Paragraph(format!(
+        "Methodology {}: {description}.",
+        config.methodology
+    ))];
+    let mut items = Vec::new();
+    settings("", &config.settings, &mut items);
+    if !items.is_empty() {
+        blocks.push(Block::List(items));
+    }
+    let mut items = vec![
+        format!("MTU: {}", config.mtu),
+        format!("Duration: {} s", config.duration),
+        format!("Interval between measurements: {} s", config.interval),
+        format!("tcp_info fields: {}", config.fields.join(", ")),
+        format!("Traced on every ACK: {}", config.probe),
+    ];
+    if let Some(system) = &manifest.system {
+        items.push(format!("Congestion control: {}", system.congestion));
+        items.push(format!("Kernel: {}", system.kernel));
+    }
+    items.extend(
+        config
+            .kernel
+            .iter()
+            .map(|(name, value)| format!("{name} = {value}")),
+    );
+    blocks.push(Block::List(items));
+    blocks
+}
+
+/// Flags a run whose manifest records conditions that may have distorted it
+fn validate(manifest: &Manifest) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if let Some((rate, true)) = manifest.loss() {
+        warnings.push(format!(
+            "measured loss rate {rate:.7} diverges from the nominal one"
+        ));
+    }
+    if let Some(drops) = manifest.drops.as_ref().filter(|e| e.oversized > 0) {
+        warnings.push(format!(
+            "{} packets larger than the MTU were dropped on top of the nominal loss",
+            drops.oversized
+        ));
+    }
+    for (short, feature, expected) in offloads(&manifest.config).features() {
+        let Some(expected) = expected else {
+            continue;
+        };
+        let expected = if expected { "on" } else { "off" };
+        let ends = manifest.system.iter().flat_map(|e| &e.offloads);
+        for (end, features) in ends {
+            match features.get(feature) {
+                Some(state) if state.starts_with(expected) => {}
+                state => warnings.push(format!(
+                    "{short} on {end} was {} instead of {expected}",
+                    state.map_or("unknown", String::as_str)
+                )),
+            }
+        }
+    }
+    warnings.extend(manifest.warnings.iter().cloned());
+    warnings
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time
+fn utc(seconds: f64) -> String {
+    let seconds = seconds as i64;
+    let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    // civil from days, after Howard Hinnant
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    let (hours, minutes, seconds) = (time / 3600, time % 3600 / 60, time % 60);
+    format!("{year}-{month:02}-{day:02} {hours:02}:{minutes:02}:{seconds:02} UTC")
+}
+
+/// Writes `report.md` and `report.html` on the runs of a grid into `out`,
+/// along with the charts the Markdown one links to
+pub fn report(grid: &Grid, out: &Path, models: &Models, style: &Style) -> Result<()> {
+    let mut runs = Vec::new();
+    for (r, p) in grid.runs() {
+        runs.push((r, p, cache::manifest(&grid.out, r, p)));
+    }
+    let mut blocks = Vec::new();
+
+    blocks.push(Block::Heading(2, "Methodology".into()));
+    // runs of a directory share a methodology, unless it was reused
+    let mut methodologies = BTreeMap::new();
+    for manifest in runs.iter().filter_map(|(_, _, e)| e.as_ref()) {
+        let key = (
+            &manifest.config.methodology,
+            manifest.config.settings.to_string(),
+        );
+        methodologies.entry(key).or_insert(manifest);
+    }
+    if methodologies.len() > 1 {
+        blocks.push(Block::Paragraph(
+            "Runs were simulated with several methodologies, each listed along with the system of its first run.".into(),
+        ));
+    }
+    for manifest in methodologies.values() {
+        blocks.extend(methodology(manifest));
+    }
+    if methodologies.is_empty() {
+        blocks.push(Block::Paragraph(
+            "No run has a manifest, so the methodology is not recorded.".into(),
+        ));
+    }
+
+    blocks.push(Block::Heading(2, "Warnings".into()));
+    let mut warnings = Vec::new();
+    for (r, p, manifest) in &runs {
+        let run = match manifest {
+            Some(manifest) => validate(manifest),
+            None => vec!["no manifest, so the run cannot be validated".into()],
+        };
+        warnings.extend(run.into_iter().map(|e| format!("r = {r}, p = {p}: {e}")));
+    }
+    blocks.push(match warnings.is_empty() {
+        true => Block::Paragraph("None.".into()),
+        false => Block::List(warnings),
+    });
+
+    blocks.push(Block::Heading(2, "Throughput".into()));
+    let summary = summarize(grid)?;
+    blocks.push(Block::Chart(summary_chart(
+        &summary,
+        grid.loss_axis,
+        models,
+        style,
+    )?));
+    let mut header = [
+        "RTT (s)",
+        "Loss rate",
+        "Measured loss rate",
+        "Simulated (MB/s)",
+    ]
+    .map(String::from)
+    .to_vec();
+    for model in &models.models {
+        header.push(format!("{} (MB/s)", model.name()));
+        header.push(format!("{} error", model.name()));
+    }
+    let mut rows = Vec::new();
+    for (r, p, manifest) in &runs {
+        let simulated = throughput(&load(&grid.out, *r, *p)?)
+            .with_context(|| format!("r = {r}, p = {p} in {}", grid.out.display()))?;
+        let measured = manifest.as_ref().and_then(|e| e.loss());
+        let mut row = vec![
+            r.to_string(),
+            p.to_string(),
+            measured.map_or(String::new(), |(rate, _)| format!("{rate:.7}")),
+            format!("{simulated:.3}"),
+        ];
+        let x = loss_rate(grid, *r, *p)?;
+        for model in &models.models {
+            let estimation = model.throughput(&models.parameters, *r, x);
+            row.push(format!("{estimation:.3}"));
+            row.push(format!(
+                "{:+.1}%",
+                (estimation - simulated) / simulated * 100.0
+            ));
+        }
+        rows.push(row);
+    }
+    blocks.push(Block::Table(header, rows));
+
+    blocks.push(Block::Heading(2, "Runs".into()));
+    let header = [
+        "RTT (s)",
+        "Loss rate",
+        "Start",
+        "Elapsed (s)",
+        "Congestion control",
+        "Netem drops",
+        "Periodic drops",
+        "Oversized drops",
+    ];
+    let mut rows = Vec::new();
+    for (r, p, manifest) in &runs {
+        let mut row = vec![r.to_string(), p.to_string()];
+        if let Some(manifest) = manifest {
+            let (timing, system) = (manifest.timing.as_ref(), manifest.system.as_ref());
+            let netem = manifest.netem.as_ref();
+            let drops = manifest.drops.as_ref();
+            row.extend([
+                timing.map_or(String::new(), |e| utc(e.start)),
+                timing.map_or(String::new(), |e| format!("{:.1}", e.elapsed)),
+                system.map_or(String::new(), |e| e.congestion.clone()),
+                netem.map_or(String::new(), |e| {
+                    e.end.drops.saturating_sub(e.start.drops).to_string()
+                }),
+                drops.map_or(String::new(), |e| e.periodic.to_string()),
+                drops.map_or(String::new(), |e| e.oversized.to_string()),
+            ]);
+        }
+        row.resize(header.len(), String::new());
+        rows.push(row);
+    }
+    blocks.push(Block::Table(header.map(String::from).to_vec(), rows));
+    for (r, p, manifest) in &runs {
+        blocks.push(Block::Heading(3, format!("r = {r}, p = {p}")));
+        let events = probe::load(&grid.out, *r, *p)?;
+        let measurements = load(&grid.out, *r, *p)?;
+        let chart = run_chart(*r, *p, &measurements, events.as_deref(), models, style)?;
+        blocks.push(Block::Chart(chart));
+        blocks.push(match manifest {
+            Some(manifest) => {
+                let manifest = serde_json::to_string_pretty(manifest)?;
+                Block::Details("Manifest".into(), manifest)
+            }
+            None => Block::Paragraph("No manifest.".into()),
+        });
+    }
+
+    // the Markdown report links to images of the charts, written alongside
+    let image = match style.format.iter().find(|e| **e != Format::Html) {
+        Some(format) => *format,
+        None => Format::Png,
+    };
+    let mut images = style.clone();
+    if !images.format.contains(&image) {
+        images.format.push(image);
+    }
+    for block in &blocks {
+        if let Block::Chart(chart) = block {
+            chart.write(out, &images)?;
+        }
+    }
+
+    let title = format!("Report on {}", name(&grid.out));
+    let mut markdown = format!("# {title}\n");
+    let mut html = String::new();
+    for block in &blocks {
+        markdown += "\n";
+        markdown += &block.markdown(image);
+        html += &block.html(style)?;
+        html += "\n";
+    }
+    write(out.join("report.md"), markdown)?;
+    let html = include_str!("report.html")
+        .replace("{title}", &escape(&title))
+        .replace("{font}", &escape(&style.font))
+        .replace("{body}", &html);
+    write(out.join("report.html"), html)?;
+    Ok(())
+}
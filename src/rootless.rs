@@ -0,0 +1,38 @@
+use std::fs::write;
+
+use anyhow::{Context, Result};
+use nix::{
+    mount::{mount, MsFlags},
+    sched::{unshare, CloneFlags},
+    unistd::{getgid, getuid},
+};
+
+/// Moves the process into new user, network and mount namespaces, in which it
+/// is root and builds the topology without touching the host. Must be called
+/// before any thread is spawned.
+pub fn enter() -> Result<()> {
+    let (uid, gid) = (getuid(), getgid());
+    unshare(CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNET | CloneFlags::CLONE_NEWNS)
+        .context("the kernel does not allow unprivileged user namespaces")?;
+    write("/proc/self/setgroups", "deny")?;
+    write("/proc/self/uid_map", format!("0 {uid} 1"))?;
+    write("/proc/self/gid_map", format!("0 {gid} 1"))?;
+    mount(
+        None::<&str>,
+        "/",
+        None::<&str>,
+        MsFlags::MS_REC | MsFlags::MS_PRIVATE,
+        None::<&str>,
+    )?;
+    // ip netns keeps its namespaces in /var/run/netns, which only the host
+    // may write to
+    mount(
+        Some("tmpfs"),
+        "/var/run",
+        Some("tmpfs"),
+        MsFlags::empty(),
+        None::<&str>,
+    )
+    .context("failed to mount a private /var/run")?;
+    Ok(())
+}
@@ -0,0 +1,216 @@
+use std::{
+    cell::RefCell,
+    fs::{write, File},
+    io::Read,
+    mem::take as take_all,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::OnceLock,
+    thread::{scope, sleep},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// How commands are run, set once at startup
+struct Settings {
+    dry_run: bool,
+    timeout: Duration,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+thread_local! {
+    /// Commands run by this thread since the transcript was last taken
+    static TRANSCRIPT: RefCell<Vec<Invocation>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Makes commands print as a shell script instead of running with
+/// `dry_run`, and kills those that run longer than `timeout`
+pub fn configure(dry_run: bool, timeout: Duration) {
+    let _ = SETTINGS.set(Settings { dry_run, timeout });
+    if dry_run {
+        println!("#!/bin/sh\nset -e");
+    }
+}
+
+fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| Settings {
+        dry_run: false,
+        timeout: Duration::from_secs(30),
+    })
+}
+
+pub fn dry_run() -> bool {
+    settings().dry_run
+}
+
+/// A command run on behalf of a run, with its outcome
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Invocation {
+    /// Network namespace the command ran in, if any
+    pub netns: Option<String>,
+    pub program: String,
+    pub args: Vec<String>,
+    /// Exit code, unset if the command was killed
+    pub status: Option<i32>,
+    pub timed_out: bool,
+    /// Wall clock time, in seconds
+    pub duration: f64,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Invocation {
+    /// The command as a shell would run it
+    pub fn line(&self) -> String {
+        let mut words = Vec::new();
+        if let Some(netns) = &self.netns {
+            words.extend(["ip", "netns", "exec", netns]);
+        }
+        words.push(&self.program);
+        words.extend(self.args.iter().map(String::as_str));
+        words.into_iter().map(quote).collect::<Vec<_>>().join(" ")
+    }
+}
+
+fn quote(word: &str) -> String {
+    let plain = |e: char| e.is_ascii_alphanumeric() || "_-./:=,+@%".contains(e);
+    if !word.is_empty() && word.chars().all(plain) {
+        word.into()
+    } else {
+        format!("'{}'", word.replace('\'', r"'\''"))
+    }
+}
+
+/// Runs a command, in a network namespace if given, returning its standard
+/// output. The command is split on whitespace, and recorded in the transcript
+/// of the calling thread along with its outcome.
+pub fn exec(command: impl AsRef<str>, netns: Option<&str>) -> Result<String> {
+    let mut words = command.as_ref().split_whitespace().map(String::from);
+    let mut invocation = Invocation {
+        netns: netns.map(Into::into),
+        program: words.next().context("empty command")?,
+        args: words.collect(),
+        status: None,
+        timed_out: false,
+        duration: 0.0,
+        stdout: String::new(),
+        stderr: String::new(),
+    };
+    let line = invocation.line();
+    if dry_run() {
+        println!("{line}");
+        return Ok(String::new());
+    }
+    let result = run(&mut invocation);
+    let stdout = invocation.stdout.clone();
+    let (status, timed_out, stderr) = (
+        invocation.status,
+        invocation.timed_out,
+        invocation.stderr.trim().to_string(),
+    );
+    TRANSCRIPT.with(|e| e.borrow_mut().push(invocation));
+    result.with_context(|| format!("failed to run `{line}`"))?;
+    match status {
+        Some(0) => Ok(stdout),
+        _ if timed_out => bail!("`{line}` timed out after {:?}", settings().timeout),
+        Some(code) => bail!("`{line}` exited with status {code}: {stderr}"),
+        None => bail!("`{line}` was killed by a signal: {stderr}"),
+    }
+}
+
+/// Runs the invocation to completion or its timeout, filling in its outcome
+fn run(invocation: &mut Invocation) -> Result<()> {
+    let mut command = match &invocation.netns {
+        Some(netns) => {
+            let mut command = Command::new("ip");
+            command.args(["netns", "exec", netns, &invocation.program]);
+            command
+        }
+        None => Command::new(&invocation.program),
+    };
+    let start = Instant::now();
+    let mut child = command
+        .args(&invocation.args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let (mut stdout, mut stderr) = (child.stdout.take().unwrap(), child.stderr.take().unwrap());
+    let timeout = settings().timeout;
+    scope(|s| {
+        let stdout = s.spawn(move || read(&mut stdout));
+        let stderr = s.spawn(move || read(&mut stderr));
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if start.elapsed() > timeout {
+                invocation.timed_out = true;
+                child.kill()?;
+                break child.wait()?;
+            }
+            sleep(Duration::from_millis(1));
+        };
+        invocation.status = status.code();
+        invocation.duration = start.elapsed().as_secs_f64();
+        invocation.stdout = stdout.join().unwrap();
+        invocation.stderr = stderr.join().unwrap();
+        Ok(())
+    })
+}
+
+fn read(pipe: &mut impl Read) -> String {
+    let mut output = Vec::new();
+    let _ = pipe.read_to_end(&mut output);
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+/// Writes to a file of procfs or sysfs, or prints how in a dry run
+pub fn write_value(path: &str, value: impl AsRef<str>) -> Result<()> {
+    let value = value.as_ref();
+    if dry_run() {
+        println!("echo {} > {}", quote(value), quote(path));
+        return Ok(());
+    }
+    write(path, value)?;
+    Ok(())
+}
+
+/// Adds a comment to the script of a dry run
+pub fn note(text: impl AsRef<str>) {
+    if dry_run() {
+        println!("# {}", text.as_ref());
+    }
+}
+
+/// Adds an operation that ran without a command, such as a netlink request,
+/// to the transcript of the calling thread
+pub fn record(invocation: Invocation) {
+    TRANSCRIPT.with(|e| e.borrow_mut().push(invocation));
+}
+
+/// Takes the commands this thread ran since the last call
+pub fn take() -> Vec<Invocation> {
+    TRANSCRIPT.with(|e| take_all(&mut *e.borrow_mut()))
+}
+
+/// Commands behind a run: those that set up the topology it ran on, shared
+/// with the other runs of its sweep, and its own
+#[derive(Serialize)]
+struct Transcript<'a> {
+    setup: &'a [Invocation],
+    run: &'a [Invocation],
+}
+
+fn path(out: &Path, r: f64, p: f64) -> PathBuf {
+    out.join(format!("{r}_{:.5}.commands.json", p))
+}
+
+pub fn save(out: &Path, r: f64, p: f64, setup: &[Invocation], run: &[Invocation]) -> Result<()> {
+    let transcript = Transcript { setup, run };
+    serde_json::to_writer_pretty(File::create(path(out, r, p))?, &transcript)?;
+    Ok(())
+}
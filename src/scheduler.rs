@@ -0,0 +1,197 @@
+use std::{
+    fs::read_to_string,
+    panic::resume_unwind,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread::{available_parallelism, scope},
+};
+
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use nix::{
+    sched::{sched_getaffinity, sched_setaffinity, CpuSet},
+    unistd::Pid,
+};
+
+use crate::{
+    cache::{store, Config},
+    methodology::{plan, prepare, simulate, Methodology, Sender, Topology},
+    runner,
+};
+
+/// Fraction of the time the CPUs a run could use spent busy past which it is
+/// flagged, as the sender then competes with the softirqs of the link and
+/// with other runs
+const BUSY: f64 = 0.8;
+
+#[derive(Args)]
+pub struct Jobs {
+    /// Runs to simulate at once, each on a topology with namespaces and
+    /// addresses of its own
+    #[arg(long, default_value_t = 1)]
+    pub jobs: usize,
+    /// CPUs to pin the jobs to, one per job in turn
+    #[arg(long, value_delimiter = ',')]
+    pub cpus: Vec<usize>,
+}
+
+impl Jobs {
+    /// Simulates and stores every point, spreading them over the jobs
+    pub fn run(
+        &self,
+        methodology: &dyn Methodology,
+        topology: &Topology,
+        sender: &Sender,
+        out: &Path,
+        points: Vec<(f64, f64)>,
+    ) -> Result<()> {
+        // a dry run prints a single script, which runs everything in turn
+        let jobs = match runner::dry_run() {
+            true => 1,
+            false => self.jobs.clamp(1, points.len().max(1)),
+        };
+        let cpus = available_parallelism()?.get();
+        // recorded in the manifest of every run, which they may have affected
+        let mut warnings = Vec::new();
+        if jobs > 1 && jobs * 2 > cpus {
+            warnings.push(format!(
+                "{jobs} jobs on {cpus} CPUs leave the senders and the softirqs of their links competing for CPU time"
+            ));
+        }
+        let allowed = sched_getaffinity(Pid::from_raw(0))?;
+        for cpu in &self.cpus {
+            if !(*cpu < CpuSet::count() && allowed.is_set(*cpu)?) {
+                bail!("CPU {cpu} is not available");
+            }
+        }
+        if self.cpus.len() < jobs && !self.cpus.is_empty() {
+            warnings.push(format!("{jobs} jobs share {} CPUs", self.cpus.len()));
+        }
+        for warning in &warnings {
+            eprintln!("warning: {warning}");
+        }
+        let queue = Mutex::new(points.into_iter());
+        let failed = AtomicBool::new(false);
+        scope(|s| {
+            let workers =
+                (0..jobs)
+                    .map(|i| {
+                        let topology = match i {
+                            0 => topology.clone(),
+                            _ => topology.sibling(),
+                        };
+                        let cpu = self.cpus.get(i % self.cpus.len().max(1)).copied();
+                        let (queue, failed, warnings) = (&queue, &failed, &warnings);
+                        s.spawn(move || {
+                            let result =
+                                work(methodology, &topology, sender, out, cpu, warnings, || {
+                                    match failed.load(Ordering::Relaxed) {
+                                        true => None,
+                                        false => queue.lock().unwrap().next(),
+                                    }
+                                });
+                            if result.is_err() {
+                                failed.store(true, Ordering::Relaxed);
+                            }
+                            result
+                        })
+                    })
+                    .collect::<Vec<_>>();
+            // reports the first failure, once every job has stopped
+            let mut result = Ok(());
+            for worker in workers {
+                let outcome = worker.join().unwrap_or_else(|e| resume_unwind(e));
+                result = result.and(outcome);
+            }
+            result
+        })
+    }
+}
+
+/// Sets up a topology and simulates the points `next` hands out on it until
+/// there are none left
+fn work(
+    methodology: &dyn Methodology,
+    topology: &Topology,
+    sender: &Sender,
+    out: &Path,
+    cpu: Option<usize>,
+    warnings: &[String],
+    next: impl Fn() -> Option<(f64, f64)>,
+) -> Result<()> {
+    if let Some(cpu) = cpu {
+        // inherited by the server and the threads of every run
+        let mut set = CpuSet::new();
+        set.set(cpu)?;
+        sched_setaffinity(Pid::from_raw(0), &set)?;
+    }
+    let _topology = prepare(methodology, topology)?;
+    let setup = runner::take();
+    while let Some((r, p)) = next() {
+        if runner::dry_run() {
+            plan(methodology, topology, sender, r, p)?;
+            continue;
+        }
+        let config = Config::new(methodology, topology, sender, r, p)?;
+        let before = CpuTime::read(cpu)?;
+        let mut run = simulate(methodology, topology, sender, r, p)?;
+        let busy = CpuTime::read(cpu)?.busy_since(&before);
+        run.warnings.extend_from_slice(warnings);
+        if busy > BUSY {
+            let cpus = match cpu {
+                Some(cpu) => format!("CPU {cpu}"),
+                None => "all CPUs".into(),
+            };
+            let warning = format!(
+                "{cpus} busy {:.0}% of the time, which may have distorted the run",
+                busy * 100.0
+            );
+            eprintln!("warning: r = {r}, p = {p}: {warning}");
+            run.warnings.push(warning);
+        }
+        store(out, config, run, &setup)?;
+    }
+    Ok(())
+}
+
+/// Time spent by a CPU, or all of them, in clock ticks
+struct CpuTime {
+    busy: u64,
+    total: u64,
+}
+
+impl CpuTime {
+    fn read(cpu: Option<usize>) -> Result<Self> {
+        let name = match cpu {
+            Some(cpu) => format!("cpu{cpu}"),
+            None => "cpu".into(),
+        };
+        let stat = read_to_string("/proc/stat")?;
+        let ticks = stat
+            .lines()
+            .find_map(|e| e.strip_prefix(&name)?.strip_prefix(' '))
+            .with_context(|| format!("no {name} in /proc/stat"))?
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<u64>, _>>()?;
+        // user, nice, system, idle, iowait, irq, softirq and steal, which
+        // guest time is already part of
+        let total = ticks.iter().take(8).sum::<u64>();
+        let idle = ticks.iter().skip(3).take(2).sum::<u64>();
+        Ok(Self {
+            busy: total - idle,
+            total,
+        })
+    }
+
+    fn busy_since(&self, before: &Self) -> f64 {
+        let total = self.total.saturating_sub(before.total);
+        match total {
+            0 => 0.0,
+            _ => self.busy.saturating_sub(before.busy) as f64 / total as f64,
+        }
+    }
+}
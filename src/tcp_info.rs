@@ -0,0 +1,160 @@
+use std::{
+    mem::{offset_of, size_of, MaybeUninit},
+    net::TcpStream,
+    os::fd::AsRawFd,
+};
+
+use anyhow::{bail, Result};
+use nix::libc::{getsockopt, SOL_TCP, TCP_INFO};
+
+macro_rules! tcp_info {
+    ($($(#[$meta:meta])* $field:ident: $ty:ty,)*) => {
+        /// `struct tcp_info` of include/uapi/linux/tcp.h, as of Linux 6.7
+        #[repr(C)]
+        #[derive(Clone, Copy)]
+        pub struct TcpInfo {
+            $($(#[$meta])* pub $field: $ty,)*
+        }
+
+        /// Names of the fields that can be written as CSV columns
+        pub const FIELDS: &[&str] = &[
+            $(stringify!($field),)*
+            "snd_wscale",
+            "rcv_wscale",
+            "delivery_rate_app_limited",
+            "fastopen_client_fail",
+        ];
+
+        impl TcpInfo {
+            /// Value of a field along with the offset at which it ends
+            fn field(&self, name: &str) -> Option<(u64, usize)> {
+                match name {
+                    $(stringify!($field) => Some((
+                        self.$field as u64,
+                        offset_of!(TcpInfo, $field) + size_of::<$ty>(),
+                    )),)*
+                    "snd_wscale" => Some(((self.wscale & 0xf) as u64, offset_of!(TcpInfo, wscale) + 1)),
+                    "rcv_wscale" => Some(((self.wscale >> 4) as u64, offset_of!(TcpInfo, wscale) + 1)),
+                    "delivery_rate_app_limited" => Some(((self.flags & 1) as u64, offset_of!(TcpInfo, flags) + 1)),
+                    "fastopen_client_fail" => Some(((self.flags >> 1 & 3) as u64, offset_of!(TcpInfo, flags) + 1)),
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+tcp_info! {
+    state: u8,
+    ca_state: u8,
+    retransmits: u8,
+    probes: u8,
+    backoff: u8,
+    options: u8,
+    /// `snd_wscale` and `rcv_wscale`, four bits each
+    wscale: u8,
+    /// `delivery_rate_app_limited` and `fastopen_client_fail`, one and two
+    /// bits respectively
+    flags: u8,
+    rto: u32,
+    ato: u32,
+    snd_mss: u32,
+    rcv_mss: u32,
+    unacked: u32,
+    sacked: u32,
+    lost: u32,
+    retrans: u32,
+    fackets: u32,
+    last_data_sent: u32,
+    last_ack_sent: u32,
+    last_data_recv: u32,
+    last_ack_recv: u32,
+    pmtu: u32,
+    rcv_ssthresh: u32,
+    rtt: u32,
+    rttvar: u32,
+    snd_ssthresh: u32,
+    snd_cwnd: u32,
+    advmss: u32,
+    reordering: u32,
+    rcv_rtt: u32,
+    rcv_space: u32,
+    total_retrans: u32,
+    pacing_rate: u64,
+    max_pacing_rate: u64,
+    bytes_acked: u64,
+    bytes_received: u64,
+    segs_out: u32,
+    segs_in: u32,
+    notsent_bytes: u32,
+    min_rtt: u32,
+    data_segs_in: u32,
+    data_segs_out: u32,
+    delivery_rate: u64,
+    busy_time: u64,
+    rwnd_limited: u64,
+    sndbuf_limited: u64,
+    delivered: u32,
+    delivered_ce: u32,
+    bytes_sent: u64,
+    bytes_retrans: u64,
+    dsack_dups: u32,
+    reord_seen: u32,
+    rcv_ooopack: u32,
+    snd_wnd: u32,
+    rcv_wnd: u32,
+    rehash: u32,
+    total_rto: u16,
+    total_rto_recoveries: u16,
+    total_rto_time: u32,
+}
+
+/// A `TcpInfo` along with how much of it the kernel filled in
+pub struct Snapshot {
+    pub info: TcpInfo,
+    pub len: usize,
+}
+
+impl Snapshot {
+    /// Value of a field, which fails if the kernel is too old to report it
+    pub fn get(&self, name: &str) -> Result<u64> {
+        match self.info.field(name) {
+            Some((value, end)) if end <= self.len => Ok(value),
+            Some(_) => bail!("tcp_info field {name} is not reported by this kernel"),
+            None => bail!("tcp_info has no field {name}"),
+        }
+    }
+}
+
+pub fn tcp_info(stream: &TcpStream) -> Result<Snapshot> {
+    unsafe {
+        let mut tcp_info = MaybeUninit::<TcpInfo>::zeroed();
+        let mut sock_len = size_of::<TcpInfo>() as u32;
+        let ret = getsockopt(
+            stream.as_raw_fd(),
+            SOL_TCP,
+            TCP_INFO,
+            tcp_info.as_mut_ptr().cast(),
+            &mut sock_len,
+        );
+        if ret != 0 {
+            Err(nix::Error::last().into())
+        } else {
+            Ok(Snapshot {
+                info: tcp_info.assume_init(),
+                len: sock_len as usize,
+            })
+        }
+    }
+}
+
+/// Resolves the names of the fields to write as CSV columns
+pub fn fields(names: &[String]) -> Result<Vec<&'static str>> {
+    names
+        .iter()
+        .map(|name| match FIELDS.iter().find(|e| **e == name) {
+            Some(field) => Ok(*field),
+            None => bail!("tcp_info has no field {name}"),
+        })
+        .collect()
+}